@@ -2,19 +2,47 @@
 import hashlib
 import hmac
 import json
+import time
 
 from argparse import ArgumentParser
 from http.server import HTTPServer, BaseHTTPRequestHandler
 
 
 SECRET = b'secret'
+# Used when playlog.signature is set to "timestamp"
+MAX_AGE = 300
+SEEN_NONCES = {}
+# IDs of stored events, a real server should keep them in a database
+SEEN_EVENTS = set()
 
 
-def verify_signature(expected_signature, data):
+def verify_signature(expected_signature, data, timestamp=None, nonce=None):
     actual_signature = hmac.new(SECRET, digestmod=hashlib.sha256)
+    if timestamp is not None or nonce is not None:
+        if timestamp is None or nonce is None:
+            return False
+        now = time.time()
+        if abs(now - int(timestamp)) > MAX_AGE:
+            return False
+        for key, seen_at in list(SEEN_NONCES.items()):
+            if now - seen_at > MAX_AGE * 2:
+                del SEEN_NONCES[key]
+        if nonce in SEEN_NONCES:
+            return False
+        SEEN_NONCES[nonce] = now
+        actual_signature.update('{}.{}.'.format(timestamp, nonce).encode())
     actual_signature.update(data)
     actual_signature = actual_signature.hexdigest()
-    return actual_signature == expected_signature
+    return hmac.compare_digest(actual_signature, expected_signature or '')
+
+
+def store_event(event, event_id):
+    # Event is sent again when response is lost, but it must be stored only once
+    if event_id is not None:
+        if event_id in SEEN_EVENTS:
+            return
+        SEEN_EVENTS.add(event_id)
+    print(event)
 
 
 class RequestHandler(BaseHTTPRequestHandler):
@@ -23,23 +51,40 @@ class RequestHandler(BaseHTTPRequestHandler):
         request_data = self.rfile.read(content_length)
 
         signature = self.headers.get('X-HMAC-Signature')
-        if verify_signature(signature, request_data):
+        timestamp = self.headers.get('X-Playlog-Timestamp')
+        nonce = self.headers.get('X-Playlog-Nonce')
+        response_data = b''
+        if verify_signature(signature, request_data, timestamp, nonce):
             try:
                 data = json.loads(request_data)
-                print(data)
-            except json.DecoderError as exc:
+            except ValueError as exc:
                 print('Failed to decode request data: {}'.format(exc))
                 status = 400
             else:
+                if isinstance(data, list):
+                    # Batch request (playlog.batch_size), Idempotency-Key is not sent, event IDs are used instead
+                    results = []
+                    for event in data:
+                        event_id = event.get('data', {}).get('id')
+                        store_event(event, event_id)
+                        results.append({'id': event_id, 'status': 200})
+                    response_data = json.dumps({'results': results}).encode()
+                else:
+                    store_event(data, self.headers.get('Idempotency-Key'))
                 # 200 status code considered ok, client will retry request otherwise
                 status = 200
         else:
             status = 403
             print('Signature verification failed')
         self.send_response(status)
-        self.send_header('Content-type', 'text/plain')
+        if response_data:
+            self.send_header('Content-type', 'application/json')
+        else:
+            # response body is not required for a single event
+            self.send_header('Content-type', 'text/plain')
+        self.send_header('Content-Length', str(len(response_data)))
         self.end_headers()
-        # response body is not required
+        self.wfile.write(response_data)
 
 
 def main():
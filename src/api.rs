@@ -1,12 +1,16 @@
 use crate::sys::{DB_functions_t, DB_metaInfo_t, DB_playItem_t};
 use ffix::{string::StringReader, Error as FfixError};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     error::Error,
-    ffi::{CStr, CString, NulError},
+    ffi::{CStr, CString, NulError, OsStr},
     fmt,
-    os::raw::{c_char, c_int},
+    os::{
+        raw::{c_char, c_int},
+        unix::ffi::OsStrExt,
+    },
+    path::PathBuf,
     ptr::null,
     str::Utf8Error,
 };
@@ -20,11 +24,15 @@ const KEY_DISC_NUMBER: &str = "disc";
 const KEY_TOTAL_DISCS: &str = "numdiscs";
 const KEY_TRACK_NUMBER: &str = "track";
 const KEY_TOTAL_TRACKS: &str = "numtracks";
+const KEY_MUSICBRAINZ_TRACK_ID: &str = "musicbrainz_trackid";
 
 #[derive(Clone, Copy, Debug)]
 pub(crate) struct Api {
     _conf_get_str:
         unsafe extern "C" fn(key: *const c_char, def: *const c_char, buffer: *mut c_char, buffer_size: c_int),
+    _conf_get_float: unsafe extern "C" fn(key: *const c_char, def: f32) -> f32,
+    _conf_get_int: unsafe extern "C" fn(key: *const c_char, def: c_int) -> c_int,
+    _get_config_dir: unsafe extern "C" fn() -> *const c_char,
     _pl_get_item_duration: unsafe extern "C" fn(it: *mut DB_playItem_t) -> f32,
     _pl_get_metadata_head: unsafe extern "C" fn(it: *mut DB_playItem_t) -> *mut DB_metaInfo_t,
     _pl_lock: unsafe extern "C" fn(),
@@ -45,6 +53,9 @@ impl Api {
         }
         Ok(Self {
             _conf_get_str: get_method!(conf_get_str),
+            _conf_get_float: get_method!(conf_get_float),
+            _conf_get_int: get_method!(conf_get_int),
+            _get_config_dir: get_method!(get_config_dir),
             _pl_get_item_duration: get_method!(pl_get_item_duration),
             _pl_get_metadata_head: get_method!(pl_get_metadata_head),
             _pl_lock: get_method!(pl_lock),
@@ -65,15 +76,40 @@ impl Api {
         match value {
             Some(value) => {
                 if value.is_empty() {
-                    Err(ConfigError::KeyMissing)
+                    Err(ConfigError::KeyMissing(key.to_string_lossy().into_owned()))
                 } else {
                     Ok(value)
                 }
             }
-            None => Err(ConfigError::KeyMissing),
+            None => Err(ConfigError::KeyMissing(key.to_string_lossy().into_owned())),
         }
     }
 
+    pub(crate) unsafe fn conf_get_float<K>(&self, key: K, default_value: f32) -> Result<f32, ConfigError>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let key = CString::new(key).map_err(ConfigError::ConvertKey)?;
+        Ok((self._conf_get_float)(key.as_ptr(), default_value))
+    }
+
+    pub(crate) unsafe fn conf_get_int<K>(&self, key: K, default_value: i32) -> Result<i32, ConfigError>
+    where
+        K: Into<Vec<u8>>,
+    {
+        let key = CString::new(key).map_err(ConfigError::ConvertKey)?;
+        Ok((self._conf_get_int)(key.as_ptr(), default_value))
+    }
+
+    pub(crate) unsafe fn get_config_dir(&self) -> Result<PathBuf, ApiError> {
+        let ptr = (self._get_config_dir)();
+        if ptr.is_null() {
+            return Err(ApiError::NoConfigDir);
+        }
+        let raw = CStr::from_ptr(ptr);
+        Ok(PathBuf::from(OsStr::from_bytes(raw.to_bytes())))
+    }
+
     unsafe fn get_metadata(&self, ptr: *mut DB_playItem_t) -> Result<HashMap<String, String>, MetadataError> {
         let mut metadata = HashMap::new();
         let mut raw_metadata = (self._pl_get_metadata_head)(ptr).as_ref();
@@ -136,22 +172,24 @@ impl Api {
             track_number: optional_u32!(KEY_TRACK_NUMBER),
             total_tracks: optional_u32!(KEY_TOTAL_TRACKS),
             duration,
+            musicbrainz_track_id: metadata.get(KEY_MUSICBRAINZ_TRACK_ID).map(String::from),
         })
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct TrackInfo {
-    artist: String,
-    album_artist: Option<String>,
-    album: String,
-    title: String,
-    year: Option<u32>,
-    disc_number: Option<u32>,
-    total_discs: Option<u32>,
-    track_number: Option<u32>,
-    total_tracks: Option<u32>,
-    duration: f32,
+    pub(crate) artist: String,
+    pub(crate) album_artist: Option<String>,
+    pub(crate) album: String,
+    pub(crate) title: String,
+    pub(crate) year: Option<u32>,
+    pub(crate) disc_number: Option<u32>,
+    pub(crate) total_discs: Option<u32>,
+    pub(crate) track_number: Option<u32>,
+    pub(crate) total_tracks: Option<u32>,
+    pub(crate) duration: f32,
+    pub(crate) musicbrainz_track_id: Option<String>,
 }
 
 struct PlaylistLock {
@@ -174,6 +212,7 @@ impl Drop for PlaylistLock {
 #[derive(Debug)]
 pub enum ApiError {
     MethodNotFound(String),
+    NoConfigDir,
 }
 
 impl Error for ApiError {}
@@ -183,6 +222,7 @@ impl fmt::Display for ApiError {
         use self::ApiError::*;
         match self {
             MethodNotFound(name) => write!(out, "method '{}' is not found in DeaDBeeF API", name),
+            NoConfigDir => write!(out, "DeaDBeeF config directory is not available"),
         }
     }
 }
@@ -190,7 +230,8 @@ impl fmt::Display for ApiError {
 #[derive(Debug)]
 pub enum ConfigError {
     ConvertKey(NulError),
-    KeyMissing,
+    InvalidValue(String, String),
+    KeyMissing(String),
     ReadString(FfixError),
 }
 
@@ -199,7 +240,8 @@ impl Error for ConfigError {
         use self::ConfigError::*;
         Some(match self {
             ConvertKey(err) => err,
-            KeyMissing => return None,
+            InvalidValue(_, _) => return None,
+            KeyMissing(_) => return None,
             ReadString(err) => err,
         })
     }
@@ -210,7 +252,8 @@ impl fmt::Display for ConfigError {
         use self::ConfigError::*;
         match self {
             ConvertKey(err) => write!(out, "could not create CString for a key: {}", err),
-            KeyMissing => write!(out, "configuration option is missing"),
+            InvalidValue(key, value) => write!(out, "configuration option '{}' has invalid value '{}'", key, value),
+            KeyMissing(key) => write!(out, "configuration option '{}' is missing", key),
             ReadString(err) => write!(out, "could not read a string from config: {}", err),
         }
     }
@@ -0,0 +1,73 @@
+use crate::{api::ConfigError, config::ConfigSection};
+use rand::Rng;
+use std::time::Duration;
+
+const DEFAULT_MAX_TRIES: i32 = 5;
+const DEFAULT_INITIAL_DELAY: i32 = 100;
+const DEFAULT_MAX_DELAY: i32 = 300_000;
+const DEFAULT_MULTIPLIER: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackoffSettings {
+    pub(crate) max_tries: u32,
+    pub(crate) initial_delay: Duration,
+    pub(crate) max_delay: Duration,
+    pub(crate) multiplier: f32,
+}
+
+impl BackoffSettings {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        macro_rules! millis {
+            ($key:expr, $default:expr) => {
+                Duration::from_millis(section.get_int($key, $default)?.max(0) as u64)
+            };
+        }
+        let max_tries = section.get_int("retry.max_tries", DEFAULT_MAX_TRIES)?.max(0) as u32;
+        let initial_delay = millis!("retry.initial_delay", DEFAULT_INITIAL_DELAY);
+        let max_delay = millis!("retry.max_delay", DEFAULT_MAX_DELAY);
+        let multiplier = section.get_float("retry.multiplier", DEFAULT_MULTIPLIER)?.max(1.0);
+        Ok(Self {
+            max_tries,
+            initial_delay,
+            max_delay: max_delay.max(initial_delay),
+            multiplier,
+        })
+    }
+}
+
+pub struct Backoff {
+    settings: BackoffSettings,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(settings: BackoffSettings) -> Self {
+        Self { settings, attempt: 0 }
+    }
+
+    pub fn set_settings(&mut self, settings: BackoffSettings) {
+        self.settings = settings;
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    // Exponential delay with "equal jitter": a random value between a half and a full delay
+    pub fn next_delay(&mut self) -> Duration {
+        let settings = &self.settings;
+        let factor = f64::from(settings.multiplier).powi(self.attempt.min(64) as i32);
+        let delay = (settings.initial_delay.as_secs_f64() * factor).min(settings.max_delay.as_secs_f64());
+        self.attempt = self.attempt.saturating_add(1);
+        let half = delay / 2.0;
+        if half > 0.0 {
+            Duration::from_secs_f64(half + rand::thread_rng().gen_range(0.0, half))
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
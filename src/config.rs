@@ -0,0 +1,169 @@
+use crate::{
+    api::{Api, ConfigError},
+    backoff::BackoffSettings,
+    event::Event,
+    queue::QueueSettings,
+    sink::SinkConfig,
+};
+use std::time::Duration;
+
+const DEFAULT_ENDPOINT: &str = "default";
+const DEFAULT_SHUTDOWN_TIMEOUT: i32 = 3000;
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub(crate) endpoints: Vec<EndpointConfig>,
+    // How long DeaDBeeF waits for publishers on exit
+    pub(crate) shutdown_timeout: Duration,
+}
+
+impl Config {
+    pub(crate) unsafe fn read(api: Api) -> Result<Self, ConfigError> {
+        let mut endpoints = Vec::new();
+        // Endpoint configured with top-level options: playlog.url, playlog.secret, etc...
+        let section = ConfigSection::new(api, "playlog.");
+        if section.get_str_opt("url")?.is_some() {
+            match EndpointConfig::read(DEFAULT_ENDPOINT, &section) {
+                Ok(endpoint) => endpoints.push(endpoint),
+                Err(err) => eprintln!("[playlog] Skipping endpoint '{}': {}", DEFAULT_ENDPOINT, err),
+            }
+        }
+        let names = section.get_str_opt("endpoints")?.unwrap_or_default();
+        for name in names.split(|c: char| c == ',' || c.is_whitespace()) {
+            if name.is_empty() {
+                continue;
+            }
+            if name == DEFAULT_ENDPOINT || !is_valid_name(name) {
+                eprintln!("[playlog] Skipping endpoint '{}': invalid name", name);
+                continue;
+            }
+            if endpoints.iter().any(|x| x.name == name) {
+                eprintln!("[playlog] Skipping endpoint '{}': duplicate name", name);
+                continue;
+            }
+            let section = ConfigSection::new(api, format!("playlog.endpoint.{}.", name));
+            match EndpointConfig::read(name, &section) {
+                Ok(endpoint) => endpoints.push(endpoint),
+                Err(err) => eprintln!("[playlog] Skipping endpoint '{}': {}", name, err),
+            }
+        }
+        let shutdown_timeout = section.get_int("shutdown_timeout", DEFAULT_SHUTDOWN_TIMEOUT)?.max(0) as u64;
+        Ok(Self {
+            endpoints,
+            shutdown_timeout: Duration::from_millis(shutdown_timeout),
+        })
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointConfig {
+    pub(crate) name: String,
+    pub(crate) events: EventFilter,
+    pub(crate) strict_ordering: bool,
+    pub(crate) queue: QueueSettings,
+    pub(crate) retry: BackoffSettings,
+    pub(crate) sink: SinkConfig,
+}
+
+impl EndpointConfig {
+    unsafe fn read(name: &str, section: &ConfigSection) -> Result<Self, ConfigError> {
+        Ok(Self {
+            name: String::from(name),
+            events: EventFilter::read(section)?,
+            strict_ordering: match section.get_str_opt("ordering")?.as_deref() {
+                None | Some("none") => false,
+                Some("strict") => true,
+                Some(value) => return Err(ConfigError::InvalidValue(section.key("ordering"), String::from(value))),
+            },
+            queue: QueueSettings::read(section)?,
+            retry: BackoffSettings::read(section)?,
+            sink: SinkConfig::read(section)?,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EventFilter {
+    start: bool,
+    stop: bool,
+}
+
+impl EventFilter {
+    unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        let value = match section.get_str_opt("events")? {
+            Some(value) => value,
+            None => {
+                return Ok(Self {
+                    start: true,
+                    stop: true,
+                })
+            }
+        };
+        let mut filter = Self {
+            start: false,
+            stop: false,
+        };
+        for item in value.split(|c: char| c == ',' || c.is_whitespace()) {
+            match item {
+                "" => continue,
+                "start" => filter.start = true,
+                "stop" => filter.stop = true,
+                _ => return Err(ConfigError::InvalidValue(section.key("events"), value)),
+            }
+        }
+        Ok(filter)
+    }
+
+    pub(crate) fn accepts(&self, event: &Event) -> bool {
+        match event {
+            Event::ConfigChanged(_) => false,
+            Event::Start(_) => self.start,
+            Event::Stop(_) => self.stop,
+        }
+    }
+}
+
+pub(crate) struct ConfigSection {
+    api: Api,
+    prefix: String,
+}
+
+impl ConfigSection {
+    fn new<P>(api: Api, prefix: P) -> Self
+    where
+        P: Into<String>,
+    {
+        Self {
+            api,
+            prefix: prefix.into(),
+        }
+    }
+
+    pub(crate) fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    pub(crate) unsafe fn get_str(&self, name: &str) -> Result<String, ConfigError> {
+        self.api.conf_get_str(self.key(name))
+    }
+
+    pub(crate) unsafe fn get_str_opt(&self, name: &str) -> Result<Option<String>, ConfigError> {
+        match self.get_str(name) {
+            Ok(value) => Ok(Some(value)),
+            Err(ConfigError::KeyMissing(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub(crate) unsafe fn get_int(&self, name: &str, default_value: i32) -> Result<i32, ConfigError> {
+        self.api.conf_get_int(self.key(name), default_value)
+    }
+
+    pub(crate) unsafe fn get_float(&self, name: &str, default_value: f32) -> Result<f32, ConfigError> {
+        self.api.conf_get_float(self.key(name), default_value)
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{event::Event, spool::SpoolError};
+use serde::Serialize;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Events rejected by a server permanently, kept for manual inspection
+pub struct DeadLetters {
+    path: PathBuf,
+}
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    failed_at: u64,
+    error: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl DeadLetters {
+    pub fn new<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn push(&self, event: &Event, error: String) -> Result<(), SpoolError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut data = serde_json::to_vec(&DeadLetter {
+            failed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0),
+            error,
+            event,
+        })?;
+        data.push(b'\n');
+        let mut file = OpenOptions::new().append(true).create(true).open(&self.path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{
+    config::{Config, EndpointConfig},
+    dead_letter::DeadLetters,
+    event::Event,
+    publisher::{Payload, Publisher},
+    queue::{self, Receiver, Sender},
+    spool::Spool,
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+pub struct Dispatcher {
+    data_dir: PathBuf,
+    shutdown_timeout: Duration,
+    workers: Vec<Worker>,
+    // Workers of removed endpoints, they are joined once finished or on stop
+    stopping: Vec<Worker>,
+}
+
+struct Worker {
+    config: EndpointConfig,
+    sender: Sender,
+    // Taken when publisher thread is started
+    receiver: Option<Receiver>,
+    publisher: Option<PublisherThread>,
+}
+
+struct PublisherThread {
+    handle: JoinHandle<()>,
+    // Disconnected when publisher thread exits
+    finished: mpsc::Receiver<()>,
+}
+
+impl Dispatcher {
+    pub fn new<P>(data_dir: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self {
+            data_dir: data_dir.into(),
+            shutdown_timeout: Duration::default(),
+            workers: Vec::new(),
+            stopping: Vec::new(),
+        }
+    }
+
+    pub fn configure(&mut self, config: Config) {
+        self.shutdown_timeout = config.shutdown_timeout;
+        self.reap();
+        let mut workers = Vec::with_capacity(config.endpoints.len());
+        let mut old_workers = std::mem::take(&mut self.workers);
+        for endpoint in config.endpoints {
+            match old_workers.iter().position(|x| x.config.name == endpoint.name) {
+                Some(idx) => {
+                    let mut worker = old_workers.remove(idx);
+                    if worker.config != endpoint {
+                        worker.sender.set_settings(endpoint.queue);
+                        if let Err(err) = worker.sender.send(Payload::Reload(Box::new(endpoint.clone()))) {
+                            eprintln!("[playlog] Failed to reload endpoint '{}': {}", endpoint.name, err);
+                        }
+                        worker.config = endpoint;
+                    }
+                    workers.push(worker);
+                }
+                None => workers.push(self.spawn(&endpoint)),
+            }
+        }
+        // Config is changed from the message thread, so removed endpoints are not waited for here
+        for worker in old_workers {
+            worker.sender.shutdown(Instant::now() + self.shutdown_timeout);
+            self.stopping.push(worker);
+        }
+        self.workers = workers;
+    }
+
+    fn reap(&mut self) {
+        let (finished, stopping) = std::mem::take(&mut self.stopping)
+            .into_iter()
+            .partition(Worker::is_finished);
+        self.stopping = stopping;
+        for worker in finished {
+            worker.join(Instant::now());
+        }
+        // Workers of the same endpoint use the same files, so each one waits until the older ones have finished
+        let mut names = HashSet::new();
+        for worker in self.stopping.iter_mut().chain(self.workers.iter_mut()) {
+            if names.insert(worker.config.name.clone()) {
+                worker.start(&self.data_dir);
+            }
+        }
+    }
+
+    fn spawn(&self, config: &EndpointConfig) -> Worker {
+        let spill = Spool::new(self.data_dir.join("spill").join(format!("{}.jsonl", config.name)));
+        let (tx, rx) = queue::channel(&config.name, config.queue, spill);
+        let mut worker = Worker {
+            config: config.clone(),
+            sender: tx,
+            receiver: Some(rx),
+            publisher: None,
+        };
+        // Publisher of a removed endpoint is still running, the new one is started by reap() once it has finished
+        if self.stopping.iter().any(|x| x.config.name == config.name) {
+            worker.sender.lock_spill();
+        } else {
+            worker.start(&self.data_dir);
+        }
+        worker
+    }
+
+    pub fn dispatch(&mut self, event: Event) {
+        self.reap();
+        for worker in &self.workers {
+            if worker.config.events.accepts(&event) {
+                if let Err(err) = worker.sender.send(Payload::Event(event.clone())) {
+                    eprintln!("[playlog] can not send event to '{}': {}", worker.config.name, err);
+                }
+            }
+        }
+    }
+
+    // Returns whether each event is queued for at least one endpoint
+    pub fn dispatch_imported(&mut self, events: Vec<Event>) -> Vec<bool> {
+        self.reap();
+        let mut queued = vec![false; events.len()];
+        for worker in &self.workers {
+            let (indexes, accepted): (Vec<usize>, Vec<Event>) = events
+                .iter()
+                .enumerate()
+                .filter(|(_, event)| worker.config.events.accepts(event))
+                .map(|(idx, event)| (idx, event.clone()))
+                .unzip();
+            if accepted.is_empty() {
+                continue;
+            }
+            match worker.sender.send_imported(accepted) {
+                Ok(()) => indexes.into_iter().for_each(|idx| queued[idx] = true),
+                Err(err) => eprintln!("[playlog] can not send events to '{}': {}", worker.config.name, err),
+            }
+        }
+        queued
+    }
+
+    pub fn stop(mut self) {
+        self.reap();
+        let deadline = Instant::now() + self.shutdown_timeout;
+        for worker in &self.workers {
+            worker.sender.shutdown(deadline);
+        }
+        // Older workers go first, so events of a worker which has not started are spilled after theirs
+        for worker in self.stopping.into_iter().chain(self.workers) {
+            worker.join(deadline);
+        }
+    }
+}
+
+impl Worker {
+    fn start(&mut self, data_dir: &Path) {
+        let receiver = match self.receiver.take() {
+            Some(receiver) => receiver,
+            None => return,
+        };
+        let name = &self.config.name;
+        let spool = Spool::new(data_dir.join("spool").join(format!("{}.jsonl", name)));
+        let dead_letters = DeadLetters::new(data_dir.join("dead_letter").join(format!("{}.jsonl", name)));
+        let (finished_tx, finished_rx) = mpsc::channel();
+        let config = self.config.clone();
+        let data_dir = data_dir.to_path_buf();
+        self.sender.unlock_spill();
+        self.publisher = Some(PublisherThread {
+            // Sink and spool are initialized in publisher thread, so the player is not blocked meanwhile
+            handle: thread::spawn(move || {
+                let _finished = finished_tx;
+                Publisher::new(&config, data_dir, receiver, spool, dead_letters).run()
+            }),
+            finished: finished_rx,
+        });
+    }
+
+    fn is_finished(&self) -> bool {
+        match self.publisher {
+            Some(ref publisher) => matches!(publisher.finished.try_recv(), Err(TryRecvError::Disconnected)),
+            None => false,
+        }
+    }
+
+    fn join(self, deadline: Instant) {
+        let publisher = match self.publisher {
+            Some(publisher) => publisher,
+            None => {
+                // Older workers of the endpoint have spilled their queued events by now, these ones follow them
+                self.sender.spill_pending();
+                eprintln!(
+                    "[playlog] Endpoint '{}' was not started, queued events will be published on next start",
+                    self.config.name
+                );
+                return;
+            }
+        };
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if let Err(RecvTimeoutError::Timeout) = publisher.finished.recv_timeout(timeout) {
+            // Publisher is stuck in a request, the thread is left to finish (or to be killed on exit) by itself
+            self.sender.spill_pending();
+            eprintln!(
+                "[playlog] Endpoint '{}' did not stop in time, unsent events will be published on next start",
+                self.config.name
+            );
+            return;
+        }
+        if let Err(err) = publisher.handle.join() {
+            eprintln!(
+                "[playlog] an error has occurred when joining a publisher thread of '{}': {:?}",
+                self.config.name, err
+            );
+        }
+    }
+}
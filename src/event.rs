@@ -1,15 +1,26 @@
 use crate::{
     api::{Api, ConfigError, TrackInfo, TrackInfoError},
+    config::Config,
     sys::{ddb_event_track_t, ddb_event_trackchange_t, DB_EV_CONFIGCHANGED, DB_EV_SONGCHANGED, DB_EV_SONGSTARTED},
 };
-use serde::Serialize;
-use std::{error::Error, fmt};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+// https://www.last.fm/api/scrobbling#when-is-a-scrobble-a-scrobble
+const SCROBBLE_MIN_DURATION: f32 = 30.0;
+const SCROBBLE_MAX_PLAY_TIME: f32 = 240.0;
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(tag = "event", content = "data")]
 #[serde(rename_all = "snake_case")]
 pub enum Event {
-    ConfigChanged(EventConfigChanged),
+    #[serde(skip)]
+    ConfigChanged(Config),
     Start(EventStart),
     Stop(EventStop),
 }
@@ -23,32 +34,46 @@ impl Event {
         _p2: u32,
     ) -> Result<Option<Event>, EventError> {
         match id {
-            DB_EV_CONFIGCHANGED => EventConfigChanged::read(api).map(|x| Some(Event::ConfigChanged(x))),
+            DB_EV_CONFIGCHANGED => Config::read(api)
+                .map(|x| Some(Event::ConfigChanged(x)))
+                .map_err(EventError::ReadConfig),
             DB_EV_SONGCHANGED => EventStop::from_context(api, ctx).map(|x| x.map(Event::Stop)),
             DB_EV_SONGSTARTED => EventStart::from_context(api, ctx).map(|x| Some(Event::Start(x))),
             _ => Ok(None),
         }
     }
-}
 
-#[derive(Debug, Serialize)]
-pub struct EventConfigChanged {
-    pub(crate) url: String,
-    pub(crate) secret: String,
-}
+    // Unique ID of event, stays the same for all attempts to deliver it
+    pub(crate) fn id(&self) -> Option<&Uuid> {
+        match self {
+            Event::ConfigChanged(_) => None,
+            Event::Start(event) => Some(&event.id),
+            Event::Stop(event) => Some(&event.id),
+        }
+    }
 
-impl EventConfigChanged {
-    unsafe fn read(api: Api) -> Result<Self, EventError> {
-        let url = api.conf_get_str("playlog.url").map_err(EventError::ReadConfig)?;
-        let secret = api.conf_get_str("playlog.secret").map_err(EventError::ReadConfig)?;
-        Ok(EventConfigChanged { url, secret })
+    pub(crate) fn set_sequence(&mut self, sequence: u64) {
+        match self {
+            Event::ConfigChanged(_) => {}
+            Event::Start(event) => event.sequence = sequence,
+            Event::Stop(event) => event.sequence = sequence,
+        }
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct EventStart {
+    // Events spooled by older versions get a new ID when loaded
+    #[serde(default = "Uuid::new_v4")]
+    pub(crate) id: Uuid,
     #[serde(flatten)]
-    track_info: TrackInfo,
+    pub(crate) track_info: TrackInfo,
+    // Older versions didn't store it, so spooled events get the time they are loaded at
+    #[serde(default = "unix_time")]
+    pub(crate) started_at: i64,
+    // Assigned by publisher, events spooled by older versions don't have it
+    #[serde(default)]
+    pub(crate) sequence: u64,
 }
 
 impl EventStart {
@@ -59,16 +84,25 @@ impl EventStart {
         }
         let raw = *ptr;
         let track_info = api.get_track_info(raw.track).map_err(EventError::ReadTrackInfo)?;
-        Ok(Self { track_info })
+        Ok(Self {
+            id: Uuid::new_v4(),
+            track_info,
+            started_at: unix_time(),
+            sequence: 0,
+        })
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct EventStop {
+    #[serde(default = "Uuid::new_v4")]
+    pub(crate) id: Uuid,
     #[serde(flatten)]
-    track_info: TrackInfo,
-    play_time: f32,
-    started_at: i64,
+    pub(crate) track_info: TrackInfo,
+    pub(crate) play_time: f32,
+    pub(crate) started_at: i64,
+    #[serde(default)]
+    pub(crate) sequence: u64,
 }
 
 impl EventStop {
@@ -82,11 +116,25 @@ impl EventStop {
             return Ok(None);
         }
         Ok(Some(Self {
+            id: Uuid::new_v4(),
             track_info: api.get_track_info(raw.from).map_err(EventError::ReadTrackInfo)?,
             play_time: raw.playtime,
             started_at: raw.started_timestamp,
+            sequence: 0,
         }))
     }
+
+    pub(crate) fn is_scrobble(&self) -> bool {
+        let duration = self.track_info.duration;
+        duration > SCROBBLE_MIN_DURATION && self.play_time >= (duration / 2.0).min(SCROBBLE_MAX_PLAY_TIME)
+    }
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0)
 }
 
 #[derive(Debug)]
@@ -0,0 +1,318 @@
+use crate::{
+    api::TrackInfo,
+    event::{Event, EventStop},
+    sink::scrobbler_log::{HEADER_TZ_UNKNOWN, HEADER_TZ_UTC, RATING_LISTENED, RATING_SKIPPED},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Error as IoError, ErrorKind as IoErrorKind, Write},
+    mem::MaybeUninit,
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+pub struct Importer {
+    history_path: PathBuf,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+}
+
+impl Importer {
+    pub fn new<P>(history_path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self {
+            history_path: history_path.into(),
+        }
+    }
+
+    // Dispatch returns whether each event is queued, listens which are not queued can be imported again
+    pub fn import_scrobbler_log<F>(&self, path: &Path, dispatch: F) -> Result<ImportSummary, ImportError>
+    where
+        F: FnOnce(Vec<Event>) -> Vec<bool>,
+    {
+        let mut history = self.load_history()?;
+        let mut summary = ImportSummary::default();
+        let mut keys = Vec::new();
+        let mut events = Vec::new();
+        let mut local_time = false;
+        let file = File::open(path)?;
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.starts_with(HEADER_TZ_UNKNOWN) {
+                local_time = true;
+            } else if line.starts_with(HEADER_TZ_UTC) {
+                local_time = false;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let event = match parse_line(&line, local_time) {
+                Ok(event) => event,
+                Err(err) => {
+                    eprintln!("[playlog] Skipping line {} of {}: {}", idx + 1, path.display(), err);
+                    summary.invalid += 1;
+                    continue;
+                }
+            };
+            let key = listen_key(&event);
+            if history.insert(key.clone()) {
+                keys.push(key);
+                events.push(event);
+            } else {
+                summary.duplicates += 1;
+            }
+        }
+        if events.is_empty() {
+            return Ok(summary);
+        }
+        let queued = dispatch(events.into_iter().map(Event::Stop).collect());
+        let keys: Vec<String> = keys
+            .into_iter()
+            .zip(queued)
+            .filter_map(|(key, queued)| if queued { Some(key) } else { None })
+            .collect();
+        if keys.is_empty() {
+            return Err(ImportError::NoEndpoint);
+        }
+        summary.imported = keys.len();
+        // History is written once events are queued (and spilled to disk if the queue is full)
+        self.append_history(&keys)?;
+        Ok(summary)
+    }
+
+    fn load_history(&self) -> Result<HashSet<String>, ImportError> {
+        let file = match File::open(&self.history_path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == IoErrorKind::NotFound => return Ok(HashSet::new()),
+            Err(err) => return Err(ImportError::Io(err)),
+        };
+        let mut history = HashSet::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if !line.is_empty() {
+                history.insert(line);
+            }
+        }
+        Ok(history)
+    }
+
+    fn append_history(&self, keys: &[String]) -> Result<(), ImportError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = self.history_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut data = keys.join("\n");
+        data.push('\n');
+        let mut file = OpenOptions::new().append(true).create(true).open(&self.history_path)?;
+        file.write_all(data.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str, local_time: bool) -> Result<EventStop, ImportError> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < 7 {
+        return Err(ImportError::InvalidLine(String::from("not enough fields")));
+    }
+    macro_rules! parse {
+        ($idx:expr, $name:expr) => {
+            fields[$idx]
+                .parse()
+                .map_err(|_| ImportError::InvalidLine(format!("invalid {}: '{}'", $name, fields[$idx])))?
+        };
+    }
+    macro_rules! required {
+        ($idx:expr, $name:expr) => {
+            match fields[$idx] {
+                "" => return Err(ImportError::InvalidLine(format!("{} is empty", $name))),
+                value => String::from(value),
+            }
+        };
+    }
+    let duration: u32 = parse!(4, "duration");
+    let play_time = match fields[5] {
+        RATING_LISTENED => duration as f32,
+        RATING_SKIPPED => 0.0,
+        value => return Err(ImportError::InvalidLine(format!("invalid rating: '{}'", value))),
+    };
+    let mut started_at: i64 = parse!(6, "timestamp");
+    if local_time {
+        started_at -= utc_offset(started_at);
+    }
+    Ok(EventStop {
+        id: Uuid::new_v4(),
+        track_info: TrackInfo {
+            artist: required!(0, "artist"),
+            album_artist: None,
+            album: String::from(fields[1]),
+            title: required!(2, "title"),
+            year: None,
+            disc_number: None,
+            total_discs: None,
+            track_number: match fields[3] {
+                "" => None,
+                _ => Some(parse!(3, "track number")),
+            },
+            total_tracks: None,
+            duration: duration as f32,
+            musicbrainz_track_id: fields.get(7).filter(|x| !x.is_empty()).map(|x| String::from(*x)),
+        },
+        play_time,
+        started_at,
+        sequence: 0,
+    })
+}
+
+fn listen_key(event: &EventStop) -> String {
+    let track_info = &event.track_info;
+    let mut hasher = Sha256::new();
+    for field in &[&track_info.artist, &track_info.album, &track_info.title] {
+        hasher.input(field.as_bytes());
+        hasher.input(b"\0");
+    }
+    hasher.input(event.started_at.to_string().as_bytes());
+    hex::encode(hasher.result())
+}
+
+// tm_gmtoff is not i64 on 32-bit targets
+#[allow(clippy::useless_conversion)]
+fn utc_offset(timestamp: i64) -> i64 {
+    let time = timestamp as libc::time_t;
+    let mut tm = MaybeUninit::<libc::tm>::zeroed();
+    unsafe {
+        if libc::localtime_r(&time, tm.as_mut_ptr()).is_null() {
+            return 0;
+        }
+        i64::from(tm.assume_init().tm_gmtoff)
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    InvalidLine(String),
+    Io(IoError),
+    NoEndpoint,
+}
+
+impl From<IoError> for ImportError {
+    fn from(err: IoError) -> Self {
+        ImportError::Io(err)
+    }
+}
+
+impl Error for ImportError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImportError::InvalidLine(_) => None,
+            ImportError::Io(err) => Some(err),
+            ImportError::NoEndpoint => None,
+        }
+    }
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::InvalidLine(message) => write!(out, "invalid line: {}", message),
+            ImportError::Io(err) => write!(out, "import IO error: {}", err),
+            ImportError::NoEndpoint => write!(out, "no endpoint accepts stop events"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<EventStop, ImportError> {
+        parse_line(line, false)
+    }
+
+    fn is_invalid(line: &str) -> bool {
+        matches!(parse(line), Err(ImportError::InvalidLine(_)))
+    }
+
+    #[test]
+    fn listened() {
+        let event = parse("Artist\tAlbum\tTitle\t3\t215\tL\t1600000000\tb1a9c0e9-d987-4042-ae91-78d6a3267d69").unwrap();
+        assert_eq!(event.track_info.artist, "Artist");
+        assert_eq!(event.track_info.album, "Album");
+        assert_eq!(event.track_info.title, "Title");
+        assert_eq!(event.track_info.track_number, Some(3));
+        assert_eq!(event.track_info.duration, 215.0);
+        assert_eq!(event.play_time, 215.0);
+        assert_eq!(event.started_at, 1_600_000_000);
+        assert_eq!(
+            event.track_info.musicbrainz_track_id.as_deref(),
+            Some("b1a9c0e9-d987-4042-ae91-78d6a3267d69")
+        );
+    }
+
+    #[test]
+    fn skipped() {
+        let event = parse("Artist\t\tTitle\t\t215\tS\t1600000000\t").unwrap();
+        assert_eq!(event.track_info.album, "");
+        assert_eq!(event.track_info.track_number, None);
+        assert_eq!(event.track_info.musicbrainz_track_id, None);
+        assert_eq!(event.play_time, 0.0);
+    }
+
+    #[test]
+    fn without_musicbrainz_id() {
+        let event = parse("Artist\tAlbum\tTitle\t1\t215\tL\t1600000000").unwrap();
+        assert_eq!(event.track_info.musicbrainz_track_id, None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(is_invalid("Artist\tAlbum\tTitle\t1\t215\tL"));
+        assert!(is_invalid("\tAlbum\tTitle\t1\t215\tL\t1600000000"));
+        assert!(is_invalid("Artist\tAlbum\t\t1\t215\tL\t1600000000"));
+        assert!(is_invalid("Artist\tAlbum\tTitle\tfirst\t215\tL\t1600000000"));
+        assert!(is_invalid("Artist\tAlbum\tTitle\t1\t3:35\tL\t1600000000"));
+        assert!(is_invalid("Artist\tAlbum\tTitle\t1\t215\tX\t1600000000"));
+        assert!(is_invalid("Artist\tAlbum\tTitle\t1\t215\tL\tyesterday"));
+    }
+
+    #[test]
+    fn key_is_stable() {
+        let first = parse("Artist\tAlbum\tTitle\t1\t215\tL\t1600000000").unwrap();
+        // Id, rating and other fields are not part of the key
+        let second = parse("Artist\tAlbum\tTitle\t2\t100\tS\t1600000000\tid").unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(listen_key(&first), listen_key(&second));
+        assert_eq!(listen_key(&first).len(), 64);
+    }
+
+    #[test]
+    fn key_is_unique() {
+        let keys: HashSet<String> = [
+            "Artist\tAlbum\tTitle\t1\t215\tL\t1600000000",
+            "Artist\tAlbum\tTitle\t1\t215\tL\t1600000001",
+            "Other\tAlbum\tTitle\t1\t215\tL\t1600000000",
+            "Artist\tOther\tTitle\t1\t215\tL\t1600000000",
+            "Artist\tAlbum\tOther\t1\t215\tL\t1600000000",
+            // Fields are separated, so moving text between them changes the key
+            "ArtistAlbum\t\tTitle\t1\t215\tL\t1600000000",
+            "Artist\tAlbumT\title\t1\t215\tL\t1600000000",
+        ]
+        .iter()
+        .map(|line| listen_key(&parse(line).unwrap()))
+        .collect();
+        assert_eq!(keys.len(), 7);
+    }
+}
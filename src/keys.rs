@@ -0,0 +1,99 @@
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, SignatureError, SECRET_KEY_LENGTH};
+use hex::FromHexError;
+use rand::rngs::OsRng;
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{Error as IoError, ErrorKind as IoErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+pub const DEFAULT_PRIVATE_KEY: &str = "ed25519.key";
+
+pub fn load_keypair(path: &Path) -> Result<Keypair, KeyError> {
+    let data = fs::read_to_string(path).map_err(|err| KeyError::Read(path.to_path_buf(), err))?;
+    let bytes = hex::decode(data.trim())?;
+    if bytes.len() != SECRET_KEY_LENGTH {
+        return Err(KeyError::InvalidLength(bytes.len()));
+    }
+    let secret = SecretKey::from_bytes(&bytes)?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+// Existing key is never overwritten, its public key is returned instead
+pub fn generate_keypair(path: &Path) -> Result<PublicKey, KeyError> {
+    match load_keypair(path) {
+        Ok(keypair) => return Ok(keypair.public),
+        Err(KeyError::Read(_, ref err)) if err.kind() == IoErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let keypair = Keypair::generate(&mut OsRng);
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?;
+    file.write_all(hex::encode(keypair.secret.as_bytes()).as_bytes())?;
+    file.sync_all()?;
+    fs::write(public_key_path(path), hex::encode(keypair.public.as_bytes()))?;
+    Ok(keypair.public)
+}
+
+pub fn public_key_path(path: &Path) -> PathBuf {
+    path.with_extension("pub")
+}
+
+#[derive(Debug)]
+pub enum KeyError {
+    Hex(FromHexError),
+    InvalidLength(usize),
+    Io(IoError),
+    Read(PathBuf, IoError),
+    Signature(SignatureError),
+}
+
+impl From<FromHexError> for KeyError {
+    fn from(err: FromHexError) -> Self {
+        KeyError::Hex(err)
+    }
+}
+
+impl From<IoError> for KeyError {
+    fn from(err: IoError) -> Self {
+        KeyError::Io(err)
+    }
+}
+
+impl From<SignatureError> for KeyError {
+    fn from(err: SignatureError) -> Self {
+        KeyError::Signature(err)
+    }
+}
+
+impl Error for KeyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::KeyError::*;
+        match self {
+            Hex(err) => Some(err),
+            InvalidLength(_) => None,
+            Io(err) => Some(err),
+            Read(_, err) => Some(err),
+            Signature(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::KeyError::*;
+        match self {
+            Hex(err) => write!(out, "private key is not a valid hex string: {}", err),
+            InvalidLength(len) => write!(out, "private key must be {} bytes long, got {}", SECRET_KEY_LENGTH, len),
+            Io(err) => write!(out, "key IO error: {}", err),
+            Read(path, err) => write!(out, "can not read private key {}: {}", path.display(), err),
+            Signature(err) => write!(out, "invalid private key: {}", err),
+        }
+    }
+}
@@ -1,23 +1,40 @@
 #![allow(clippy::missing_safety_doc)]
 use ffix::string::expose_string;
 use libc::{malloc, memset};
-use reqwest::blocking::Client;
 use std::{
+    ffi::OsStr,
     mem::size_of,
+    os::{
+        raw::{c_char, c_int},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
     ptr::null_mut,
-    sync::mpsc::{channel, Sender},
-    thread::{self, JoinHandle},
+    slice,
+    sync::{Mutex, MutexGuard},
 };
 
 mod api;
+mod backoff;
+mod config;
+mod dead_letter;
+mod dispatcher;
 mod event;
+mod import;
+mod keys;
 mod publisher;
+mod queue;
+mod sequence;
+mod sink;
+mod spool;
 mod sys;
 
 use self::{
     api::Api,
+    config::Config,
+    dispatcher::Dispatcher,
     event::Event,
-    publisher::{Payload, Publisher},
+    import::Importer,
     sys::{DB_functions_t, DB_plugin_t, DB_API_VERSION_MAJOR, DB_API_VERSION_MINOR, DB_PLUGIN_MISC},
 };
 
@@ -28,15 +45,26 @@ const PLUGIN_NAME: &str = "Playlog";
 const PLUGIN_DESCRIPTION: &str = r#"Sends played songs information to an HTTP server"#;
 const PLUGIN_COPYRIGHT: &str = env!("CARGO_PKG_AUTHORS");
 const PLUGIN_WEBSITE: &str = "https://github.com/rossnomann/deadbeef-playlog";
+const IMPORT_COMMAND: &str = "--playlog-import";
+const KEYGEN_COMMAND: &str = "--playlog-keygen";
 const PLUGIN_CONFIGDIALOG: &str = r#"property URL entry playlog.url "";
-property Secret entry playlog.secret "";"#;
+property Secret entry playlog.secret "";
+property "Signature scheme (body or timestamp)" entry playlog.signature "body";
+property "Event types" entry playlog.events "start stop";
+property "Additional endpoints" entry playlog.endpoints "";
+property "Max tries" entry playlog.retry.max_tries 5;
+property "Initial retry delay (ms)" entry playlog.retry.initial_delay 100;
+property "Max retry delay (ms)" entry playlog.retry.max_delay 300000;
+property "Retry delay multiplier" entry playlog.retry.multiplier 2;
+property "Shutdown timeout (ms)" entry playlog.shutdown_timeout 3000;"#;
 
 static mut CONTEXT: Option<Context> = None;
 
 struct Context {
     api: Api,
-    sender: Sender<Payload>,
-    publisher: JoinHandle<()>,
+    data_dir: PathBuf,
+    dispatcher: Mutex<Dispatcher>,
+    importer: Importer,
 }
 
 #[no_mangle]
@@ -56,11 +84,12 @@ pub unsafe extern "C" fn playlog_load(api: *mut DB_functions_t) -> *mut DB_plugi
         };
     }
 
-    let (tx, rx) = channel();
     let api = abort!(Api::new(api));
-    let url = abort!(api.conf_get_str("playlog.url"), "Failed to get url");
-    let secret = abort!(api.conf_get_str("playlog.secret"), "Failed to get secret");
-    let publisher = abort!(Publisher::new(Client::new(), url, secret.as_bytes(), rx));
+    let config = abort!(Config::read(api), "Failed to read config");
+    let config_dir = abort!(api.get_config_dir(), "Failed to get config directory");
+    if config.endpoints.is_empty() {
+        eprintln!("[playlog] No endpoints configured");
+    }
 
     let raw_ptr = {
         let size = size_of::<DB_plugin_t>();
@@ -86,7 +115,7 @@ pub unsafe extern "C" fn playlog_load(api: *mut DB_functions_t) -> *mut DB_plugi
     raw.website = abort!(expose_string(PLUGIN_WEBSITE), "Failed to set plugin website");
     raw.configdialog = abort!(expose_string(PLUGIN_CONFIGDIALOG), "Failed to set plugin configdialog");
     raw.command = None;
-    raw.exec_cmdline = None;
+    raw.exec_cmdline = Some(on_exec_cmdline);
     raw.start = Some(on_start);
     raw.stop = Some(on_stop);
     raw.connect = Some(on_connect);
@@ -94,11 +123,15 @@ pub unsafe extern "C" fn playlog_load(api: *mut DB_functions_t) -> *mut DB_plugi
     raw.get_actions = None;
     raw.message = Some(on_message);
 
-    let publisher = thread::spawn(move || publisher.run());
+    let data_dir = config_dir.join(PLUGIN_ID);
+    let importer = Importer::new(data_dir.join("import").join("scrobbler_log.history"));
+    let mut dispatcher = Dispatcher::new(data_dir.clone());
+    dispatcher.configure(config);
     CONTEXT = Some(Context {
         api,
-        sender: tx,
-        publisher,
+        data_dir,
+        dispatcher: Mutex::new(dispatcher),
+        importer,
     });
 
     raw_ptr
@@ -116,14 +149,9 @@ unsafe extern "C" fn on_stop() -> i32 {
             return 0;
         }
     };
-    if let Err(err) = context.sender.send(Payload::Stop) {
-        eprintln!("[playlog] can not send event: {}", err);
-    }
-    if let Err(err) = context.publisher.join() {
-        eprintln!(
-            "[playlog] an error has occurred when joining a publisher thread: {:?}",
-            err
-        );
+    match context.dispatcher.into_inner() {
+        Ok(dispatcher) => dispatcher.stop(),
+        Err(err) => err.into_inner().stop(),
     }
     0
 }
@@ -145,13 +173,74 @@ unsafe extern "C" fn on_message(id: u32, ctx: usize, p1: u32, p2: u32) -> i32 {
         }
     };
     match Event::from_raw(context.api, id, ctx, p1, p2) {
-        Ok(Some(event)) => {
-            if let Err(err) = context.sender.send(Payload::Event(event)) {
-                eprintln!("[playlog] can not send event: {}", err);
-            }
-        }
+        Ok(Some(Event::ConfigChanged(config))) => lock_dispatcher(context).configure(config),
+        Ok(Some(event)) => lock_dispatcher(context).dispatch(event),
         Ok(None) => { /* noop */ }
         Err(err) => eprintln!("[playlog] An error has occurred when handling event: {}", err),
     }
     0
 }
+
+unsafe extern "C" fn on_exec_cmdline(cmdline: *const c_char, cmdline_size: c_int) -> c_int {
+    let context = match CONTEXT {
+        Some(ref context) => context,
+        None => {
+            eprintln!("[playlog] Failed to get context");
+            return 0;
+        }
+    };
+    if cmdline.is_null() || cmdline_size <= 0 {
+        return 0;
+    }
+    // Arguments are separated by NUL bytes
+    let data = slice::from_raw_parts(cmdline as *const u8, cmdline_size as usize);
+    let mut args = data.split(|x| *x == 0).filter(|x| !x.is_empty());
+    while let Some(arg) = args.next() {
+        if arg == IMPORT_COMMAND.as_bytes() {
+            match args.next() {
+                Some(path) => import_scrobbler_log(context, Path::new(OsStr::from_bytes(path))),
+                None => eprintln!("[playlog] {} requires a path to .scrobbler.log", IMPORT_COMMAND),
+            }
+        } else if arg == KEYGEN_COMMAND.as_bytes() {
+            generate_keypair(context);
+        }
+    }
+    0
+}
+
+fn import_scrobbler_log(context: &Context, path: &Path) {
+    // Dispatcher is locked only to queue parsed events, so a large log does not block the player
+    match context
+        .importer
+        .import_scrobbler_log(path, |events| lock_dispatcher(context).dispatch_imported(events))
+    {
+        Ok(summary) => eprintln!(
+            "[playlog] Imported {} listens from {} ({} duplicates, {} invalid lines skipped)",
+            summary.imported,
+            path.display(),
+            summary.duplicates,
+            summary.invalid
+        ),
+        Err(err) => eprintln!("[playlog] Failed to import {}: {}", path.display(), err),
+    }
+}
+
+fn generate_keypair(context: &Context) {
+    let path = context.data_dir.join(keys::DEFAULT_PRIVATE_KEY);
+    match keys::generate_keypair(&path) {
+        Ok(public_key) => {
+            eprintln!(
+                "[playlog] Ed25519 private key: {}, public key is saved to {}",
+                path.display(),
+                keys::public_key_path(&path).display()
+            );
+            println!("{}", hex::encode(public_key.as_bytes()));
+        }
+        Err(err) => eprintln!("[playlog] Failed to generate Ed25519 key pair: {}", err),
+    }
+}
+
+fn lock_dispatcher(context: &Context) -> MutexGuard<'_, Dispatcher> {
+    // Publisher threads own all the state, a poisoned lock leaves the dispatcher usable
+    context.dispatcher.lock().unwrap_or_else(|err| err.into_inner())
+}
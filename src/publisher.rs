@@ -1,172 +1,343 @@
-use crate::event::Event;
-use hmac::{crypto_mac::InvalidKeyLength, Hmac, Mac};
-use reqwest::{
-    blocking::Client,
-    header::{HeaderName, HeaderValue, InvalidHeaderValue, CONTENT_TYPE},
-    Error as ReqwestError, StatusCode,
+use crate::{
+    backoff::{Backoff, BackoffSettings},
+    config::EndpointConfig,
+    dead_letter::DeadLetters,
+    event::Event,
+    queue::Receiver,
+    sequence::Sequence,
+    sink::{Sink, SinkConfig, SinkError, UnavailableSink},
+    spool::Spool,
+};
+use std::{
+    path::PathBuf,
+    sync::mpsc::RecvTimeoutError,
+    time::{Duration, Instant},
 };
-use serde_json::Error as JsonError;
-use sha2::Sha256;
-use std::{error::Error, fmt, sync::mpsc::Receiver, thread::sleep, time::Duration};
-
-const MAX_TRIES: u64 = 5;
 
 pub enum Payload {
     Event(Event),
+    Reload(Box<EndpointConfig>),
     Stop,
 }
 
 pub struct Publisher {
-    client: Client,
-    receiver: Receiver<Payload>,
-    url: String,
-    secret: Hmac<Sha256>,
+    name: String,
+    data_dir: PathBuf,
+    sink: Box<dyn Sink>,
+    // Config of a sink which could not be created yet
+    pending_sink: Option<SinkConfig>,
+    receiver: Receiver,
+    retry: BackoffSettings,
+    strict_ordering: bool,
     error_queue: Vec<Event>,
+    spool: Spool,
+    // Spool which could not be loaded must not be overwritten
+    spool_loaded: bool,
+    dead_letters: DeadLetters,
+    sequence: Sequence,
+    redelivery: Backoff,
+    redeliver_at: Option<Instant>,
+    paused_until: Option<Instant>,
 }
 
 impl Publisher {
-    pub fn new<U>(client: Client, url: U, secret: &[u8], receiver: Receiver<Payload>) -> Result<Self, PublisherError>
-    where
-        U: Into<String>,
-    {
-        Ok(Self {
-            client,
-            url: url.into(),
-            secret: Hmac::new_varkey(secret)?,
+    pub fn new(
+        config: &EndpointConfig,
+        data_dir: PathBuf,
+        receiver: Receiver,
+        spool: Spool,
+        dead_letters: DeadLetters,
+    ) -> Self {
+        let mut publisher = Self {
+            name: config.name.clone(),
+            sequence: Sequence::load(data_dir.join("sequence").join(&config.name)),
+            data_dir,
+            sink: Box::new(UnavailableSink::new(String::from("sink is not created yet"))),
+            pending_sink: None,
+            retry: config.retry,
+            strict_ordering: config.strict_ordering,
             receiver,
             error_queue: Vec::new(),
-        })
+            spool,
+            spool_loaded: false,
+            dead_letters,
+            redelivery: Backoff::new(config.retry),
+            redeliver_at: None,
+            paused_until: None,
+        };
+        // Events are queued even if sink or spool is not available, both are tried again on redelivery
+        publisher.build_sink(&config.sink);
+        if publisher.load_spool() {
+            // Events spooled by older versions got new IDs on load, they must not change on next load
+            if !publisher.error_queue.is_empty() {
+                publisher.save_error_queue();
+                publisher.redeliver_at = Some(Instant::now());
+            }
+        } else {
+            publisher.schedule_redelivery();
+        }
+        publisher
     }
 
     pub fn run(mut self) {
         loop {
-            match self.receiver.recv() {
-                Ok(Payload::Event(Event::ConfigChanged(event))) => {
-                    self.url = event.url;
-                    match Hmac::new_varkey(event.secret.as_bytes()) {
-                        Ok(secret) => {
-                            self.secret = secret;
+            let payload = match self.redeliver_at {
+                Some(redeliver_at) => {
+                    let timeout = redeliver_at.saturating_duration_since(Instant::now());
+                    match self.receiver.recv_timeout(timeout) {
+                        Ok(payload) => payload,
+                        Err(RecvTimeoutError::Timeout) => {
+                            self.publish_error_queue();
+                            continue;
                         }
-                        Err(err) => {
-                            eprintln!("[playlog] Failed to reload secret: {}", err);
+                        Err(RecvTimeoutError::Disconnected) => {
+                            eprintln!(
+                                "[playlog] Failed to receive an event for '{}': channel is disconnected",
+                                self.name
+                            );
+                            break;
                         }
                     }
                 }
-                Ok(Payload::Event(event)) => {
-                    if let Err(err) = self.try_publish_event(&event) {
-                        eprintln!("[playlog] Failed to publish an event: {}", err);
-                        self.error_queue.push(event);
+                None => match self.receiver.recv() {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        eprintln!("[playlog] Failed to receive an event for '{}': {}", self.name, err);
+                        break;
                     }
+                },
+            };
+            match payload {
+                Payload::Reload(config) => {
+                    self.build_sink(&config.sink);
+                    self.retry = config.retry;
+                    self.strict_ordering = config.strict_ordering;
+                    self.redelivery.set_settings(config.retry);
                 }
-                Ok(Payload::Stop) => {
-                    for event in &self.error_queue {
-                        if let Err(err) = self.publish_event(&event) {
-                            eprintln!("[playlog] Failed to publish an event: {}", err);
+                Payload::Event(mut event) => {
+                    // Only events accepted by the endpoint are numbered, so a gap means a lost event
+                    event.set_sequence(self.sequence.next());
+                    self.retry_pending_sink();
+                    // Event is spooled before it is sent, so it is not lost if publisher is stopped meanwhile
+                    let has_older = !self.error_queue.is_empty();
+                    self.error_queue.push(event.clone());
+                    self.save_error_queue();
+                    // Server asked to wait, the event must not overtake older ones, sink is not created
+                    // or publisher is stopping, so it is sent with the error queue later
+                    if self.is_paused()
+                        || (self.strict_ordering && has_older)
+                        || self.pending_sink.is_some()
+                        || self.receiver.deadline().is_some()
+                    {
+                        if self.redeliver_at.is_none() {
+                            self.schedule_redelivery();
+                        }
+                        continue;
+                    }
+                    // Server is known to be unavailable, so don't retry until the redelivery time
+                    let result = if self.redeliver_at.is_some() {
+                        self.sink.publish(&event)
+                    } else {
+                        self.try_publish_event(&event)
+                    };
+                    match result {
+                        Ok(()) => {
+                            self.error_queue.pop();
+                            self.save_error_queue();
+                            if !self.error_queue.is_empty() {
+                                self.publish_error_queue();
+                            }
+                        }
+                        Err(err) if err.is_permanent() => {
+                            self.error_queue.pop();
+                            self.save_error_queue();
+                            self.reject(&event, &err);
+                        }
+                        Err(err) => {
+                            eprintln!("[playlog] Failed to publish an event to '{}': {}", self.name, err);
+                            if let Some(delay) = err.retry_after() {
+                                self.pause(delay);
+                            } else if self.redeliver_at.is_none() {
+                                self.schedule_redelivery();
+                            }
                         }
                     }
-                    break;
                 }
-                Err(err) => {
-                    eprintln!("[playlog] Failed to receive an event: {}", err);
+                Payload::Stop => {
+                    // Dispatcher stops waiting at the deadline, the queue is saved in spool anyway
+                    let in_time = !matches!(self.receiver.deadline(), Some(deadline) if deadline <= Instant::now());
+                    if !self.error_queue.is_empty() && !self.is_paused() && in_time {
+                        self.publish_error_queue();
+                    }
+                    break;
                 }
             }
         }
     }
 
-    fn try_publish_event(&self, event: &Event) -> Result<(), PublisherError> {
-        let mut current_try = 0;
-        loop {
-            match self.publish_event(event) {
-                Ok(()) => return Ok(()),
+    fn publish_error_queue(&mut self) {
+        self.retry_pending_sink();
+        if self.pending_sink.is_some() || !self.load_spool() {
+            self.schedule_redelivery();
+            return;
+        }
+        // Requests in progress are not interrupted, but no new ones are made after the shutdown deadline
+        let receiver = &self.receiver;
+        let stop = || matches!(receiver.deadline(), Some(deadline) if deadline <= Instant::now());
+        let results = self.sink.publish_batch(&self.error_queue, &stop);
+        let mut retry_after = None;
+        // Delivered and rejected events are removed, failed and not sent ones stay in the queue
+        let mut done = Vec::with_capacity(results.len());
+        for (event, result) in self.error_queue.iter().zip(results) {
+            done.push(match result {
+                Ok(()) => true,
+                Err(err) if err.is_permanent() => {
+                    self.reject(event, &err);
+                    true
+                }
                 Err(err) => {
-                    if current_try == MAX_TRIES {
-                        return Err(err);
-                    }
-                    eprintln!("[playlog] Failed to publish an event: {}, trying again...", err);
-                    sleep(Duration::from_millis(100 * current_try));
-                    current_try += 1;
+                    eprintln!("[playlog] Failed to publish a queued event to '{}': {}", self.name, err);
+                    retry_after = retry_after.or_else(|| err.retry_after());
+                    false
                 }
-            }
+            });
         }
-    }
-
-    fn publish_event(&self, event: &Event) -> Result<(), PublisherError> {
-        let data = serde_json::to_vec(&event)?;
-        let mut secret = self.secret.clone();
-        secret.input(&data);
-        let secret = secret.result();
-        let rep = self
-            .client
-            .post(&self.url)
-            .header(
-                HeaderName::from_static("x-hmac-signature"),
-                HeaderValue::from_str(&hex::encode(secret.code()))?,
-            )
-            .header(CONTENT_TYPE, "application/json")
-            .body(data)
-            .send()?;
-        let status = rep.status();
-        if !status.is_success() {
-            Err(PublisherError::RequestFailed(status))
+        if done.contains(&true) {
+            let mut done = done.into_iter();
+            self.error_queue.retain(|_| !done.next().unwrap_or(false));
+            self.save_error_queue();
+        }
+        if self.error_queue.is_empty() {
+            self.redelivery.reset();
+            self.redeliver_at = None;
+            self.paused_until = None;
+        } else if let Some(delay) = retry_after {
+            self.pause(delay);
         } else {
-            Ok(())
+            self.schedule_redelivery();
         }
     }
-}
 
-#[derive(Debug)]
-pub enum PublisherError {
-    InvalidHeaderValue(InvalidHeaderValue),
-    InvalidKeyLength(InvalidKeyLength),
-    Json(JsonError),
-    Reqwest(ReqwestError),
-    RequestFailed(StatusCode),
-}
+    fn pause(&mut self, delay: Duration) {
+        // Zero or past Retry-After must not make publisher retry in a loop
+        let delay = delay.max(self.redelivery.next_delay());
+        eprintln!(
+            "[playlog] '{}' asked to retry after {}s, {} queued event(s) will be published then",
+            self.name,
+            delay.as_secs(),
+            self.error_queue.len()
+        );
+        let until = Instant::now() + delay;
+        self.paused_until = Some(until);
+        self.redeliver_at = Some(until);
+    }
 
-impl From<InvalidHeaderValue> for PublisherError {
-    fn from(err: InvalidHeaderValue) -> Self {
-        PublisherError::InvalidHeaderValue(err)
+    fn is_paused(&self) -> bool {
+        matches!(self.paused_until, Some(until) if until > Instant::now())
     }
-}
 
-impl From<InvalidKeyLength> for PublisherError {
-    fn from(err: InvalidKeyLength) -> Self {
-        PublisherError::InvalidKeyLength(err)
+    fn schedule_redelivery(&mut self) {
+        let delay = self.redelivery.next_delay();
+        eprintln!(
+            "[playlog] {} queued event(s) will be published to '{}' again in {:.1}s",
+            self.error_queue.len(),
+            self.name,
+            delay.as_secs_f32()
+        );
+        self.redeliver_at = Some(Instant::now() + delay);
     }
-}
 
-impl From<JsonError> for PublisherError {
-    fn from(err: JsonError) -> Self {
-        PublisherError::Json(err)
+    fn reject(&self, event: &Event, err: &SinkError) {
+        eprintln!(
+            "[playlog] Event was rejected by '{}' and will not be sent again: {}",
+            self.name, err
+        );
+        match self.dead_letters.push(event, err.to_string()) {
+            Ok(()) => eprintln!(
+                "[playlog] Rejected event is saved to {}",
+                self.dead_letters.path().display()
+            ),
+            Err(err) => eprintln!("[playlog] Failed to save rejected event of '{}': {}", self.name, err),
+        }
     }
-}
 
-impl From<ReqwestError> for PublisherError {
-    fn from(err: ReqwestError) -> Self {
-        PublisherError::Reqwest(err)
+    fn build_sink(&mut self, config: &SinkConfig) {
+        match config.build(&self.name, &self.data_dir) {
+            Ok(sink) => {
+                if self.pending_sink.take().is_some() {
+                    eprintln!("[playlog] Sink of '{}' is created", self.name);
+                }
+                self.sink = sink;
+            }
+            Err(err) => {
+                eprintln!(
+                    "[playlog] Failed to create sink of '{}': {}, events are queued until it is created",
+                    self.name, err
+                );
+                self.sink = Box::new(UnavailableSink::new(err.to_string()));
+                self.pending_sink = Some(config.clone());
+            }
+        }
     }
-}
 
-impl Error for PublisherError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        match self {
-            PublisherError::InvalidHeaderValue(err) => Some(err),
-            PublisherError::InvalidKeyLength(_) => None,
-            PublisherError::Json(err) => Some(err),
-            PublisherError::Reqwest(err) => Some(err),
-            PublisherError::RequestFailed(_) => None,
+    fn retry_pending_sink(&mut self) {
+        if let Some(config) = self.pending_sink.clone() {
+            self.build_sink(&config);
+        }
+    }
+
+    // Returns false if spool is still not loaded
+    fn load_spool(&mut self) -> bool {
+        if self.spool_loaded {
+            return true;
+        }
+        match self.spool.load() {
+            Ok(mut events) => {
+                // Spooled events are older than the ones received since start
+                events.append(&mut self.error_queue);
+                self.error_queue = events;
+                self.spool_loaded = true;
+                true
+            }
+            Err(err) => {
+                eprintln!("[playlog] Failed to load error queue of '{}': {}", self.name, err);
+                false
+            }
         }
     }
-}
 
-impl fmt::Display for PublisherError {
-    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            PublisherError::InvalidHeaderValue(err) => write!(out, "could not set request header: {}", err),
-            PublisherError::InvalidKeyLength(err) => write!(out, "secret key error: {}", err),
-            PublisherError::Json(err) => write!(out, "can not serialize JSON: {}", err),
-            PublisherError::Reqwest(err) => write!(out, "failed to send HTTP request: {}", err),
-            PublisherError::RequestFailed(status) => write!(out, "server respond with {} status code", status),
+    fn save_error_queue(&mut self) {
+        if !self.load_spool() {
+            return;
+        }
+        if let Err(err) = self.spool.save(&self.error_queue) {
+            eprintln!("[playlog] Failed to save error queue of '{}': {}", self.name, err);
+        }
+    }
+
+    fn try_publish_event(&mut self, event: &Event) -> Result<(), SinkError> {
+        let mut backoff = Backoff::new(self.retry);
+        loop {
+            match self.sink.publish(event) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    // Retrying before the time requested by server would only make things worse
+                    if err.is_permanent()
+                        || err.retry_after().is_some()
+                        || backoff.attempt() + 1 >= self.retry.max_tries
+                    {
+                        return Err(err);
+                    }
+                    eprintln!(
+                        "[playlog] Failed to publish an event to '{}': {}, trying again...",
+                        self.name, err
+                    );
+                    // Event is already spooled, so it is left for redelivery when publisher is stopping
+                    if self.receiver.wait_stop(backoff.next_delay()) {
+                        return Err(err);
+                    }
+                }
+            }
         }
     }
 }
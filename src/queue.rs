@@ -0,0 +1,680 @@
+use crate::{api::ConfigError, config::ConfigSection, event::Event, publisher::Payload, spool::Spool};
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    sync::{
+        mpsc::{RecvError, RecvTimeoutError},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+const DEFAULT_CAPACITY: i32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    // Events are appended to a file and loaded back when the queue is drained
+    Spill,
+    DropOldest,
+    DropNewest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueueSettings {
+    pub(crate) capacity: usize,
+    pub(crate) overflow: OverflowPolicy,
+}
+
+impl QueueSettings {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        let overflow = match section.get_str_opt("queue.overflow")?.as_deref() {
+            None | Some("spill") => OverflowPolicy::Spill,
+            Some("drop_oldest") => OverflowPolicy::DropOldest,
+            Some("drop_newest") => OverflowPolicy::DropNewest,
+            Some(value) => {
+                return Err(ConfigError::InvalidValue(
+                    section.key("queue.overflow"),
+                    String::from(value),
+                ))
+            }
+        };
+        Ok(Self {
+            capacity: section.get_int("queue.capacity", DEFAULT_CAPACITY)?.max(1) as usize,
+            overflow,
+        })
+    }
+}
+
+// Bounded queue between dispatcher and publisher, only events count towards capacity
+pub fn channel(name: &str, settings: QueueSettings, spill: Spool) -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        name: String::from(name),
+        state: Mutex::new(State {
+            payloads: VecDeque::new(),
+            events: 0,
+            settings,
+            spill,
+            spilled: 0,
+            spill_loaded: false,
+            spill_locked: false,
+            dropped: 0,
+            deadline: None,
+            disconnected: false,
+            closed: false,
+        }),
+        available: Condvar::new(),
+    });
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+struct Shared {
+    name: String,
+    state: Mutex<State>,
+    available: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+struct Entry {
+    payload: Payload,
+    // Imported events and events loaded from the spill file are never dropped
+    droppable: bool,
+}
+
+struct State {
+    payloads: VecDeque<Entry>,
+    events: usize,
+    settings: QueueSettings,
+    spill: Spool,
+    spilled: usize,
+    // Spill file is read by publisher thread, so dispatcher is not blocked by a large file
+    spill_loaded: bool,
+    // Spill file is still used by the previous publisher of the endpoint, events are kept in memory meanwhile
+    spill_locked: bool,
+    dropped: u64,
+    // Set when publisher is asked to stop
+    deadline: Option<Instant>,
+    disconnected: bool,
+    closed: bool,
+}
+
+impl State {
+    fn push_event(&mut self, name: &str, event: Event) {
+        let full = self.events >= self.settings.capacity;
+        match self.settings.overflow {
+            // Spilled events are newer than queued ones, so the next events follow them to keep the order
+            OverflowPolicy::Spill if (full || self.spilled > 0) && !self.spill_locked => {
+                self.spill_events(name, vec![event]);
+                return;
+            }
+            OverflowPolicy::DropOldest if full => {
+                let oldest = self
+                    .payloads
+                    .iter()
+                    .position(|x| x.droppable && matches!(x.payload, Payload::Event(_)));
+                self.drop_events(name, 1);
+                match oldest {
+                    Some(idx) => {
+                        self.payloads.remove(idx);
+                        self.events -= 1;
+                    }
+                    // Only events which can not be dropped are queued, so the new one is dropped instead
+                    None => return,
+                }
+            }
+            OverflowPolicy::DropNewest if full => {
+                self.drop_events(name, 1);
+                return;
+            }
+            _ => {}
+        }
+        self.payloads.push_back(Entry {
+            payload: Payload::Event(event),
+            droppable: true,
+        });
+        self.events += 1;
+    }
+
+    // Imported events are never dropped, the ones which do not fit into the queue are spilled in one write
+    fn push_imported(&mut self, name: &str, events: Vec<Event>) {
+        let mut spilled = Vec::new();
+        for event in events {
+            let fits = self.events < self.settings.capacity || self.spill_locked;
+            if spilled.is_empty() && self.spilled == 0 && fits {
+                self.payloads.push_back(Entry {
+                    payload: Payload::Event(event),
+                    droppable: false,
+                });
+                self.events += 1;
+            } else {
+                spilled.push(event);
+            }
+        }
+        if !spilled.is_empty() {
+            self.spill_events(name, spilled);
+        }
+    }
+
+    fn spill_events(&mut self, name: &str, events: Vec<Event>) {
+        match self.spill.append(&events) {
+            Ok(()) => self.spilled += events.len(),
+            Err(err) => {
+                eprintln!("[playlog] Failed to spill events of '{}': {}", name, err);
+                self.drop_events(name, events.len() as u64);
+            }
+        }
+    }
+
+    fn unspill(&mut self, name: &str) {
+        let result = self.spill.load().and_then(|mut events| {
+            let rest = events.split_off(events.len().min(self.settings.capacity));
+            self.spill.save(&rest)?;
+            Ok((events, rest.len()))
+        });
+        match result {
+            Ok((events, spilled)) => {
+                self.spilled = spilled;
+                self.events += events.len();
+                self.payloads.extend(events.into_iter().map(|event| Entry {
+                    payload: Payload::Event(event),
+                    droppable: false,
+                }));
+            }
+            Err(err) => {
+                eprintln!("[playlog] Failed to load spilled events of '{}': {}", name, err);
+                let spilled = self.spilled;
+                self.spilled = 0;
+                self.drop_events(name, spilled as u64);
+            }
+        }
+    }
+
+    fn load_spill(&mut self, name: &str) {
+        self.spill_loaded = true;
+        let mut spilled = match self.spill.load() {
+            Ok(events) => events,
+            // The file is kept, events in it are loaded together with the next spilled ones
+            Err(err) => {
+                eprintln!("[playlog] Failed to load spilled events of '{}': {}", name, err);
+                return;
+            }
+        };
+        // Events spilled since start are already counted, the rest is left from the previous session
+        let previous = spilled.len().saturating_sub(self.spilled);
+        if previous > 0 && self.events > 0 {
+            // Events of the previous session are older than the ones queued since start
+            let newer = spilled.split_off(previous);
+            let queued = self.take_queued();
+            let count = queued.len();
+            spilled.extend(queued);
+            spilled.extend(newer);
+            if let Err(err) = self.spill.save(&spilled) {
+                eprintln!("[playlog] Failed to spill queued events of '{}': {}", name, err);
+                self.drop_events(name, count as u64);
+                self.spilled = spilled.len() - count;
+                return;
+            }
+        }
+        self.spilled = spilled.len();
+    }
+
+    // Moves queued events to the spill file, they are older than the spilled ones, so they go first
+    fn spill_pending(&mut self, name: &str) {
+        if !self.spill_loaded {
+            self.load_spill(name);
+        }
+        if self.events == 0 {
+            return;
+        }
+        let mut events = self.take_queued();
+        let count = events.len();
+        let result = self.spill.load().and_then(|spilled| {
+            events.extend(spilled);
+            self.spill.save(&events)
+        });
+        match result {
+            Ok(()) => self.spilled = events.len(),
+            Err(err) => {
+                eprintln!("[playlog] Failed to spill queued events of '{}': {}", name, err);
+                self.drop_events(name, count as u64);
+            }
+        }
+    }
+
+    fn take_queued(&mut self) -> Vec<Event> {
+        let mut events = Vec::with_capacity(self.events);
+        let mut payloads = VecDeque::with_capacity(self.payloads.len() - self.events);
+        for entry in self.payloads.drain(..) {
+            match entry.payload {
+                Payload::Event(event) => events.push(event),
+                _ => payloads.push_back(entry),
+            }
+        }
+        self.payloads = payloads;
+        self.events = 0;
+        events
+    }
+
+    fn drop_events(&mut self, name: &str, count: u64) {
+        self.dropped += count;
+        eprintln!(
+            "[playlog] Queue of '{}' is full, {} event(s) dropped ({} in total)",
+            name, count, self.dropped
+        );
+    }
+}
+
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    pub fn send(&self, payload: Payload) -> Result<(), Disconnected> {
+        let mut state = self.shared.lock();
+        if state.closed {
+            return Err(Disconnected);
+        }
+        match payload {
+            Payload::Event(event) => state.push_event(&self.shared.name, event),
+            // Control payloads are never dropped
+            payload => state.payloads.push_back(Entry {
+                payload,
+                droppable: false,
+            }),
+        }
+        drop(state);
+        self.shared.available.notify_one();
+        Ok(())
+    }
+
+    // Imported listens are recorded in import history, so they are spilled instead of being dropped
+    pub fn send_imported(&self, events: Vec<Event>) -> Result<(), Disconnected> {
+        let mut state = self.shared.lock();
+        if state.closed {
+            return Err(Disconnected);
+        }
+        state.push_imported(&self.shared.name, events);
+        drop(state);
+        self.shared.available.notify_one();
+        Ok(())
+    }
+
+    pub fn set_settings(&self, settings: QueueSettings) {
+        self.shared.lock().settings = settings;
+    }
+
+    // Asks publisher to stop, events received after this are not sent but saved for the next session
+    pub fn shutdown(&self, deadline: Instant) {
+        let mut state = self.shared.lock();
+        state.deadline = Some(deadline);
+        state.payloads.push_back(Entry {
+            payload: Payload::Stop,
+            droppable: false,
+        });
+        drop(state);
+        self.shared.available.notify_one();
+    }
+
+    pub fn lock_spill(&self) {
+        self.shared.lock().spill_locked = true;
+    }
+
+    pub fn unlock_spill(&self) {
+        self.shared.lock().spill_locked = false;
+    }
+
+    // Moves events which publisher has not received yet to the spill file, regardless of the spill lock
+    pub fn spill_pending(&self) {
+        self.shared.lock().spill_pending(&self.shared.name);
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.shared.lock().disconnected = true;
+        self.shared.available.notify_all();
+    }
+}
+
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    pub fn deadline(&self) -> Option<Instant> {
+        self.shared.lock().deadline
+    }
+
+    // Sleeps until timeout expires, returns true early if publisher is asked to stop
+    pub fn wait_stop(&self, timeout: Duration) -> bool {
+        let until = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if state.deadline.is_some() || state.disconnected {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= until {
+                return false;
+            }
+            state = self
+                .shared
+                .available
+                .wait_timeout(state, until - now)
+                .unwrap_or_else(|err| err.into_inner())
+                .0;
+        }
+    }
+
+    pub fn recv(&self) -> Result<Payload, RecvError> {
+        self.recv_deadline(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Payload, RecvTimeoutError> {
+        self.recv_deadline(Some(Instant::now() + timeout))
+    }
+
+    fn recv_deadline(&self, deadline: Option<Instant>) -> Result<Payload, RecvTimeoutError> {
+        let mut state = self.shared.lock();
+        if !state.spill_loaded {
+            state.load_spill(&self.shared.name);
+        }
+        loop {
+            // Spilled events stay on disk during shutdown, they are delivered on next start
+            if state.events == 0 && state.spilled > 0 && state.deadline.is_none() {
+                state.unspill(&self.shared.name);
+            }
+            if let Some(entry) = state.payloads.pop_front() {
+                if let Payload::Event(_) = entry.payload {
+                    state.events -= 1;
+                }
+                return Ok(entry.payload);
+            }
+            if state.disconnected {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            state = match deadline {
+                None => self.shared.available.wait(state).unwrap_or_else(|err| err.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    self.shared
+                        .available
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|err| err.into_inner())
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+    }
+}
+
+#[derive(Debug)]
+pub struct Disconnected;
+
+impl Error for Disconnected {}
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        write!(out, "publisher thread is stopped")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::TrackInfo,
+        event::{Event, EventStop},
+    };
+    use std::{
+        fs,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use uuid::Uuid;
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "playlog-queue-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let _ = fs::remove_dir_all(&path);
+            Self(path)
+        }
+
+        fn spill(&self) -> Spool {
+            Spool::new(self.0.join("spill.jsonl"))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn event(n: i64) -> Event {
+        Event::Stop(EventStop {
+            id: Uuid::new_v4(),
+            track_info: TrackInfo {
+                artist: String::from("artist"),
+                album_artist: None,
+                album: String::from("album"),
+                title: String::from("title"),
+                year: None,
+                disc_number: None,
+                total_discs: None,
+                track_number: None,
+                total_tracks: None,
+                duration: 180.0,
+                musicbrainz_track_id: None,
+            },
+            play_time: 180.0,
+            started_at: n,
+            sequence: 0,
+        })
+    }
+
+    fn events(numbers: &[i64]) -> Vec<Event> {
+        numbers.iter().map(|n| event(*n)).collect()
+    }
+
+    fn open(dir: &TempDir, capacity: usize, overflow: OverflowPolicy) -> (Sender, Receiver) {
+        channel("test", QueueSettings { capacity, overflow }, dir.spill())
+    }
+
+    fn send(sender: &Sender, numbers: &[i64]) {
+        for event in events(numbers) {
+            sender.send(Payload::Event(event)).unwrap();
+        }
+    }
+
+    // Returns numbers of received events until the queue is empty, None stands for stop
+    fn recv_all(receiver: &Receiver) -> Vec<Option<i64>> {
+        let mut received = Vec::new();
+        while let Ok(payload) = receiver.recv_timeout(TIMEOUT) {
+            received.push(match payload {
+                Payload::Event(Event::Stop(event)) => Some(event.started_at),
+                Payload::Stop => None,
+                _ => panic!("unexpected payload"),
+            });
+        }
+        received
+    }
+
+    fn recv_one(receiver: &Receiver) -> Option<i64> {
+        match receiver.recv_timeout(TIMEOUT) {
+            Ok(Payload::Event(Event::Stop(event))) => Some(event.started_at),
+            _ => None,
+        }
+    }
+
+    fn some(numbers: &[i64]) -> Vec<Option<i64>> {
+        numbers.iter().map(|n| Some(*n)).collect()
+    }
+
+    #[test]
+    fn spill_keeps_order() {
+        let dir = TempDir::new();
+        let (tx, rx) = open(&dir, 2, OverflowPolicy::Spill);
+        send(&tx, &[1, 2, 3]);
+        assert_eq!(recv_one(&rx), Some(1));
+        // There is room in the queue, but the event must follow the spilled one
+        send(&tx, &[4]);
+        assert_eq!(recv_all(&rx), some(&[2, 3, 4]));
+        assert_eq!(tx.shared.lock().dropped, 0);
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let dir = TempDir::new();
+        let (tx, rx) = open(&dir, 2, OverflowPolicy::DropOldest);
+        send(&tx, &[1, 2, 3]);
+        assert_eq!(recv_all(&rx), some(&[2, 3]));
+        assert_eq!(tx.shared.lock().dropped, 1);
+    }
+
+    #[test]
+    fn drop_newest() {
+        let dir = TempDir::new();
+        let (tx, rx) = open(&dir, 2, OverflowPolicy::DropNewest);
+        send(&tx, &[1, 2, 3]);
+        assert_eq!(recv_all(&rx), some(&[1, 2]));
+        assert_eq!(tx.shared.lock().dropped, 1);
+    }
+
+    #[test]
+    fn imported_are_not_dropped() {
+        for &overflow in &[OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            let dir = TempDir::new();
+            let (tx, rx) = open(&dir, 2, overflow);
+            tx.send_imported(events(&[1, 2, 3])).unwrap();
+            assert_eq!(tx.shared.lock().spilled, 1);
+            // The queue is full of events which can not be dropped, so the new one is dropped
+            send(&tx, &[4]);
+            assert_eq!(recv_all(&rx), some(&[1, 2, 3]), "{:?}", overflow);
+            assert_eq!(tx.shared.lock().dropped, 1);
+        }
+    }
+
+    #[test]
+    fn drop_oldest_skips_imported() {
+        let dir = TempDir::new();
+        let (tx, rx) = open(&dir, 2, OverflowPolicy::DropOldest);
+        send(&tx, &[1]);
+        tx.send_imported(events(&[2])).unwrap();
+        send(&tx, &[3]);
+        assert_eq!(recv_all(&rx), some(&[2, 3]));
+    }
+
+    #[test]
+    fn spilled_events_are_not_dropped() {
+        let dir = TempDir::new();
+        let (tx, rx) = open(&dir, 2, OverflowPolicy::Spill);
+        send(&tx, &[1, 2, 3, 4]);
+        tx.set_settings(QueueSettings {
+            capacity: 2,
+            overflow: OverflowPolicy::DropOldest,
+        });
+        assert_eq!(recv_one(&rx), Some(1));
+        assert_eq!(recv_one(&rx), Some(2));
+        // 3 and 4 are loaded from the spill file, so the oldest new event is dropped instead of them
+        assert_eq!(recv_one(&rx), Some(3));
+        send(&tx, &[5, 6]);
+        assert_eq!(recv_all(&rx), some(&[4, 6]));
+    }
+
+    #[test]
+    fn locked_spill_is_not_used() {
+        let dir = TempDir::new();
+        // Events spilled by the previous publisher of the endpoint
+        dir.spill().append(&events(&[1, 2])).unwrap();
+        let (tx, rx) = open(&dir, 2, OverflowPolicy::Spill);
+        tx.lock_spill();
+        send(&tx, &[3, 4, 5]);
+        tx.send_imported(events(&[6])).unwrap();
+        assert_eq!(dir.spill().load().unwrap().len(), 2);
+        tx.unlock_spill();
+        send(&tx, &[7]);
+        assert_eq!(recv_all(&rx), some(&[1, 2, 3, 4, 5, 6, 7]));
+    }
+
+    #[test]
+    fn spill_pending_keeps_stop() {
+        let dir = TempDir::new();
+        {
+            let (tx, rx) = open(&dir, 10, OverflowPolicy::Spill);
+            send(&tx, &[1, 2]);
+            tx.shutdown(Instant::now());
+            tx.spill_pending();
+            // Events stay on disk during shutdown, stop is still delivered
+            assert_eq!(recv_all(&rx), vec![None]);
+        }
+        let (_tx, rx) = open(&dir, 10, OverflowPolicy::Spill);
+        assert_eq!(recv_all(&rx), some(&[1, 2]));
+    }
+
+    #[test]
+    fn spill_pending_puts_queued_events_first() {
+        let dir = TempDir::new();
+        {
+            let (tx, _rx) = open(&dir, 2, OverflowPolicy::Spill);
+            send(&tx, &[1, 2, 3]);
+            tx.shutdown(Instant::now());
+            tx.spill_pending();
+        }
+        let (_tx, rx) = open(&dir, 2, OverflowPolicy::Spill);
+        assert_eq!(recv_all(&rx), some(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn events_spilled_before_restart_go_first() {
+        let dir = TempDir::new();
+        dir.spill().append(&events(&[1, 2])).unwrap();
+        let (tx, rx) = open(&dir, 10, OverflowPolicy::Spill);
+        // Spill file is read by receiver, so the new event is queued in memory first
+        send(&tx, &[3]);
+        assert_eq!(recv_all(&rx), some(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn recv_after_disconnect() {
+        let dir = TempDir::new();
+        let (tx, rx) = open(&dir, 10, OverflowPolicy::Spill);
+        send(&tx, &[1]);
+        drop(tx);
+        assert_eq!(recv_one(&rx), Some(1));
+        assert!(matches!(rx.recv(), Err(RecvError)));
+    }
+
+    #[test]
+    fn wait_stop() {
+        let dir = TempDir::new();
+        let (tx, rx) = open(&dir, 10, OverflowPolicy::Spill);
+        assert!(!rx.wait_stop(TIMEOUT));
+        let waiter = std::thread::spawn(move || rx.wait_stop(Duration::from_secs(10)));
+        tx.shutdown(Instant::now() + Duration::from_secs(1));
+        assert!(waiter.join().unwrap());
+    }
+}
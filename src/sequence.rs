@@ -0,0 +1,80 @@
+use std::{
+    fs::{self, File},
+    io::{Error as IoError, ErrorKind as IoErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Per-endpoint event counter, every number is persisted, so numbers keep growing after restart without gaps
+pub struct Sequence {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    value: u64,
+}
+
+impl Sequence {
+    pub fn load<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let value = match read(&path) {
+            Ok(value) => value,
+            Err(err) => {
+                // Current time in milliseconds is greater than any number given before,
+                // unless the player has produced more than one event per millisecond
+                let value = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|x| x.as_millis() as u64)
+                    .unwrap_or(0);
+                eprintln!(
+                    "[playlog] Failed to load sequence number from {}: {}, numbering continues from {}",
+                    path.display(),
+                    err,
+                    value
+                );
+                value
+            }
+        };
+        Self {
+            path,
+            tmp_path: PathBuf::from(tmp_path),
+            value,
+        }
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.value += 1;
+        if let Err(err) = self.save() {
+            eprintln!(
+                "[playlog] Failed to save sequence number to {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+        self.value
+    }
+
+    fn save(&self) -> Result<(), IoError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = File::create(&self.tmp_path)?;
+        write!(file, "{}", self.value)?;
+        file.sync_all()?;
+        fs::rename(&self.tmp_path, &self.path)
+    }
+}
+
+fn read(path: &Path) -> Result<u64, IoError> {
+    match fs::read_to_string(path) {
+        Ok(data) => data
+            .trim()
+            .parse()
+            .map_err(|_| IoError::new(IoErrorKind::InvalidData, format!("invalid value: '{}'", data.trim()))),
+        Err(err) if err.kind() == IoErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{
+    api::ConfigError,
+    config::ConfigSection,
+    sink::{response_text, SinkError},
+};
+use reqwest::{blocking::Client, header::HeaderValue, StatusCode};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+// Token is refreshed a bit earlier than it expires to avoid sending a request with a stale one
+const OAUTH2_EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AuthConfig {
+    None,
+    Bearer(String),
+    Basic(String, Option<String>),
+    OAuth2(OAuth2Config),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OAuth2Config {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+}
+
+impl AuthConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        Ok(match section.get_str_opt("auth")?.as_deref() {
+            None | Some("none") => AuthConfig::None,
+            Some("bearer") => AuthConfig::Bearer(section.get_str("auth.token")?),
+            Some("basic") => {
+                AuthConfig::Basic(section.get_str("auth.username")?, section.get_str_opt("auth.password")?)
+            }
+            Some("oauth2") => AuthConfig::OAuth2(OAuth2Config {
+                token_url: section.get_str("auth.token_url")?,
+                client_id: section.get_str("auth.client_id")?,
+                client_secret: section.get_str("auth.client_secret")?,
+                scope: section.get_str_opt("auth.scope")?,
+            }),
+            Some(value) => return Err(ConfigError::InvalidValue(section.key("auth"), String::from(value))),
+        })
+    }
+}
+
+pub struct Auth {
+    config: AuthConfig,
+    token: Option<AccessToken>,
+}
+
+struct AccessToken {
+    value: String,
+    expires_at: Option<Instant>,
+}
+
+impl Auth {
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            config: config.clone(),
+            token: None,
+        }
+    }
+
+    pub fn authorization(&mut self, client: &Client) -> Result<Option<HeaderValue>, SinkError> {
+        let value = match self.config {
+            AuthConfig::None => return Ok(None),
+            AuthConfig::Bearer(ref token) => format!("Bearer {}", token),
+            AuthConfig::Basic(ref username, ref password) => {
+                let credentials = format!("{}:{}", username, password.as_deref().unwrap_or_default());
+                format!("Basic {}", base64::encode(credentials))
+            }
+            AuthConfig::OAuth2(ref config) => {
+                let expired = match self.token {
+                    Some(AccessToken {
+                        expires_at: Some(expires_at),
+                        ..
+                    }) => Instant::now() + OAUTH2_EXPIRY_MARGIN >= expires_at,
+                    Some(_) => false,
+                    None => true,
+                };
+                if expired {
+                    self.token = Some(request_token(client, config)?);
+                }
+                match self.token {
+                    Some(ref token) => format!("Bearer {}", token.value),
+                    None => return Ok(None),
+                }
+            }
+        };
+        let mut value = HeaderValue::from_str(&value)?;
+        value.set_sensitive(true);
+        Ok(Some(value))
+    }
+
+    // Returns true when the request may succeed with new credentials
+    pub fn reset(&mut self) -> bool {
+        match self.config {
+            AuthConfig::OAuth2(_) => self.token.take().is_some(),
+            _ => false,
+        }
+    }
+}
+
+fn request_token(client: &Client, config: &OAuth2Config) -> Result<AccessToken, SinkError> {
+    let mut params = vec![("grant_type", "client_credentials")];
+    if let Some(ref scope) = config.scope {
+        params.push(("scope", scope.as_str()));
+    }
+    let requested_at = Instant::now();
+    let rep = client
+        .post(&config.token_url)
+        .basic_auth(&config.client_id, Some(&config.client_secret))
+        .form(&params)
+        .send()?;
+    let status = rep.status();
+    let body = rep.text()?;
+    if !status.is_success() {
+        return Err(match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(rep) => SinkError::OAuth2(status, rep.message()),
+            // Token endpoint failures are not related to events, so they are never permanent
+            Err(_) => SinkError::OAuth2(status, response_text(body.as_bytes())),
+        });
+    }
+    let rep: TokenResponse = serde_json::from_str(&body)
+        .map_err(|err| SinkError::InvalidResponse(format!("can not parse token response: {}", err)))?;
+    if !rep.token_type.eq_ignore_ascii_case("bearer") {
+        return Err(SinkError::OAuth2(
+            StatusCode::OK,
+            format!("unsupported token type: {}", rep.token_type),
+        ));
+    }
+    Ok(AccessToken {
+        value: rep.access_token,
+        expires_at: rep.expires_in.map(|x| requested_at + Duration::from_secs(x)),
+    })
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+impl ErrorResponse {
+    fn message(&self) -> String {
+        match self.error_description {
+            Some(ref description) => format!("{}: {}", self.error, description),
+            None => self.error.clone(),
+        }
+    }
+}
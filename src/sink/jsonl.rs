@@ -0,0 +1,144 @@
+use crate::{
+    api::ConfigError,
+    config::ConfigSection,
+    event::Event,
+    sink::{Sink, SinkError},
+};
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{Error as IoError, ErrorKind as IoErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECONDS_PER_DAY: u64 = 86400;
+const DEFAULT_KEEP: i32 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonlConfig {
+    path: Option<PathBuf>,
+    max_size: u64,
+    rotate_daily: bool,
+    keep: u32,
+}
+
+impl JsonlConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        Ok(Self {
+            path: section.get_str_opt("path")?.map(PathBuf::from),
+            max_size: section.get_int("max_size", 0)?.max(0) as u64,
+            rotate_daily: section.get_int("rotate_daily", 0)? != 0,
+            keep: section.get_int("keep", DEFAULT_KEEP)?.max(0) as u32,
+        })
+    }
+}
+
+pub struct JsonlSink {
+    path: PathBuf,
+    max_size: u64,
+    rotate_daily: bool,
+    keep: u32,
+    file: Option<OpenFile>,
+}
+
+struct OpenFile {
+    file: File,
+    size: u64,
+    day: u64,
+}
+
+impl JsonlSink {
+    pub fn new(config: &JsonlConfig, name: &str, data_dir: &Path) -> Result<Self, SinkError> {
+        let path = match config.path {
+            Some(ref path) => path.clone(),
+            None => data_dir.join(format!("{}.jsonl", name)),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Self {
+            path,
+            max_size: config.max_size,
+            rotate_daily: config.rotate_daily,
+            keep: config.keep,
+            file: None,
+        })
+    }
+
+    fn open(&self) -> Result<OpenFile, IoError> {
+        let file = OpenOptions::new().append(true).create(true).open(&self.path)?;
+        let metadata = file.metadata()?;
+        let day = match metadata.modified() {
+            Ok(modified) if metadata.len() > 0 => day_of(modified),
+            _ => day_of(SystemTime::now()),
+        };
+        Ok(OpenFile {
+            file,
+            size: metadata.len(),
+            day,
+        })
+    }
+
+    fn rotated_path(&self, idx: u32) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{}", idx));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self) -> Result<(), IoError> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for idx in (1..self.keep).rev() {
+            match fs::rename(self.rotated_path(idx), self.rotated_path(idx + 1)) {
+                Ok(()) => {}
+                Err(err) if err.kind() == IoErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+
+    fn needs_rotation(&self, file: &OpenFile, len: u64) -> bool {
+        if file.size == 0 {
+            return false;
+        }
+        let size_exceeded = self.max_size > 0 && file.size + len > self.max_size;
+        let day_changed = self.rotate_daily && file.day != day_of(SystemTime::now());
+        size_exceeded || day_changed
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), IoError> {
+        let len = data.len() as u64;
+        // File is not put back on error, so a broken handle is reopened on next write
+        let mut file = match self.file.take() {
+            Some(file) => file,
+            None => self.open()?,
+        };
+        if self.needs_rotation(&file, len) {
+            drop(file);
+            self.rotate()?;
+            file = self.open()?;
+        }
+        file.file.write_all(data)?;
+        file.size += len;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+impl Sink for JsonlSink {
+    fn publish(&mut self, event: &Event) -> Result<(), SinkError> {
+        let mut data = serde_json::to_vec(&event)?;
+        data.push(b'\n');
+        self.write(&data)?;
+        Ok(())
+    }
+}
+
+fn day_of(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or(0)
+}
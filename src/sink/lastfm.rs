@@ -0,0 +1,207 @@
+use crate::{
+    api::{ConfigError, TrackInfo},
+    config::ConfigSection,
+    event::{Event, EventStop},
+    sink::{proxy::ProxyConfig, response_text, update_now_playing, Sink, SinkError},
+};
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind as IoErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+const DEFAULT_API_ROOT: &str = "https://ws.audioscrobbler.com/2.0/";
+
+const ERROR_INVALID_SESSION_KEY: u32 = 9;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LastFmConfig {
+    api_root: String,
+    api_key: String,
+    api_secret: String,
+    username: String,
+    password: String,
+    proxy: ProxyConfig,
+}
+
+impl LastFmConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        Ok(Self {
+            api_root: section
+                .get_str_opt("api_root")?
+                .unwrap_or_else(|| String::from(DEFAULT_API_ROOT)),
+            api_key: section.get_str("api_key")?,
+            api_secret: section.get_str("api_secret")?,
+            username: section.get_str("username")?,
+            password: section.get_str("password")?,
+            proxy: ProxyConfig::read(section)?,
+        })
+    }
+}
+
+pub struct LastFmSink {
+    client: Client,
+    config: LastFmConfig,
+    session_path: PathBuf,
+    session_key: Option<String>,
+}
+
+impl LastFmSink {
+    pub fn new(config: &LastFmConfig, session_path: PathBuf) -> Result<Self, SinkError> {
+        let session_key = match fs::read_to_string(&session_path) {
+            Ok(key) => Some(key.trim().to_string()).filter(|x| !x.is_empty()),
+            Err(err) if err.kind() == IoErrorKind::NotFound => None,
+            Err(err) => return Err(SinkError::Io(err)),
+        };
+        Ok(Self {
+            client: config.proxy.apply(Client::builder())?.build()?,
+            config: config.clone(),
+            session_path,
+            session_key,
+        })
+    }
+
+    fn session_key(&mut self) -> Result<String, SinkError> {
+        if let Some(ref key) = self.session_key {
+            return Ok(key.clone());
+        }
+        let rep: SessionResponse = self.call(
+            "auth.getMobileSession",
+            vec![
+                ("username", self.config.username.clone()),
+                ("password", self.config.password.clone()),
+            ],
+        )?;
+        save_session_key(&self.session_path, &rep.session.key)?;
+        self.session_key = Some(rep.session.key.clone());
+        Ok(rep.session.key)
+    }
+
+    fn call_with_session<T>(&mut self, method: &str, params: Vec<(&str, String)>) -> Result<T, SinkError>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let mut params = params;
+        params.push(("sk", self.session_key()?));
+        match self.call(method, params) {
+            Err(SinkError::LastFm(ERROR_INVALID_SESSION_KEY, message)) => {
+                // Session was revoked, authenticate again on next call
+                self.session_key = None;
+                if let Err(err) = fs::remove_file(&self.session_path) {
+                    if err.kind() != IoErrorKind::NotFound {
+                        eprintln!("[playlog] Failed to remove Last.fm session: {}", err);
+                    }
+                }
+                Err(SinkError::LastFm(ERROR_INVALID_SESSION_KEY, message))
+            }
+            result => result,
+        }
+    }
+
+    fn call<T>(&self, method: &str, params: Vec<(&str, String)>) -> Result<T, SinkError>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let mut params = params;
+        params.push(("method", String::from(method)));
+        params.push(("api_key", self.config.api_key.clone()));
+        params.sort_by(|a, b| a.0.cmp(b.0));
+        let mut signature = String::new();
+        for (key, value) in &params {
+            signature.push_str(key);
+            signature.push_str(value);
+        }
+        signature.push_str(&self.config.api_secret);
+        params.push(("api_sig", format!("{:x}", md5::compute(signature))));
+        params.push(("format", String::from("json")));
+        let rep = self.client.post(&self.config.api_root).form(&params).send()?;
+        let status = rep.status();
+        let body = rep.text()?;
+        if let Ok(ErrorResponse { error, message }) = serde_json::from_str(&body) {
+            return Err(SinkError::LastFm(error, message));
+        }
+        if !status.is_success() {
+            return Err(SinkError::RequestFailed(status, response_text(body.as_bytes())));
+        }
+        // Unexpected body is a server (or proxy) failure, not a problem of the event
+        serde_json::from_str(&body)
+            .map_err(|err| SinkError::InvalidResponse(format!("can not parse Last.fm response: {}", err)))
+    }
+
+    fn update_now_playing(&mut self, track_info: &TrackInfo) -> Result<(), SinkError> {
+        let _: serde_json::Value = self.call_with_session("track.updateNowPlaying", track_params(track_info))?;
+        Ok(())
+    }
+
+    fn scrobble(&mut self, event: &EventStop) -> Result<(), SinkError> {
+        let mut params = track_params(&event.track_info);
+        params.push(("timestamp", event.started_at.to_string()));
+        let _: serde_json::Value = self.call_with_session("track.scrobble", params)?;
+        Ok(())
+    }
+}
+
+impl Sink for LastFmSink {
+    fn publish(&mut self, event: &Event) -> Result<(), SinkError> {
+        match event {
+            Event::ConfigChanged(_) => Ok(()),
+            Event::Start(event) => update_now_playing("Last.fm", || self.update_now_playing(&event.track_info)),
+            Event::Stop(event) => {
+                if event.is_scrobble() {
+                    self.scrobble(event)
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+fn track_params(track_info: &TrackInfo) -> Vec<(&'static str, String)> {
+    let mut params = vec![
+        ("artist", track_info.artist.clone()),
+        ("track", track_info.title.clone()),
+        ("album", track_info.album.clone()),
+        ("duration", (track_info.duration.round() as u32).to_string()),
+    ];
+    if let Some(ref album_artist) = track_info.album_artist {
+        params.push(("albumArtist", album_artist.clone()));
+    }
+    if let Some(track_number) = track_info.track_number {
+        params.push(("trackNumber", track_number.to_string()));
+    }
+    params
+}
+
+fn save_session_key(path: &Path, key: &str) -> Result<(), SinkError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(key.as_bytes())?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: u32,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SessionResponse {
+    session: Session,
+}
+
+#[derive(Deserialize)]
+struct Session {
+    key: String,
+}
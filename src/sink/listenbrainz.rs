@@ -0,0 +1,180 @@
+use crate::{
+    api::{ConfigError, TrackInfo},
+    config::ConfigSection,
+    event::Event,
+    sink::{proxy::ProxyConfig, response_text, retry_after, update_now_playing, Sink, SinkError},
+};
+use reqwest::{
+    blocking::Client,
+    header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_API_ROOT: &str = "https://api.listenbrainz.org";
+const SUBMIT_LISTENS_PATH: &str = "/1/submit-listens";
+const SUBMISSION_CLIENT: &str = "deadbeef-playlog";
+const SUBMISSION_CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+const MEDIA_PLAYER: &str = "DeaDBeeF";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ListenType {
+    Import,
+    PlayingNow,
+    Single,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListenBrainzConfig {
+    api_root: String,
+    token: String,
+    listen_type: ListenType,
+    proxy: ProxyConfig,
+}
+
+impl ListenBrainzConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        let listen_type = match section.get_str_opt("listen_type")?.as_deref() {
+            None | Some("single") => ListenType::Single,
+            Some("import") => ListenType::Import,
+            Some(value) => {
+                return Err(ConfigError::InvalidValue(
+                    section.key("listen_type"),
+                    String::from(value),
+                ))
+            }
+        };
+        Ok(Self {
+            api_root: section
+                .get_str_opt("api_root")?
+                .unwrap_or_else(|| String::from(DEFAULT_API_ROOT)),
+            token: section.get_str("token")?,
+            listen_type,
+            proxy: ProxyConfig::read(section)?,
+        })
+    }
+}
+
+pub struct ListenBrainzSink {
+    client: Client,
+    url: String,
+    authorization: HeaderValue,
+    listen_type: ListenType,
+}
+
+impl ListenBrainzSink {
+    pub fn new(config: &ListenBrainzConfig) -> Result<Self, SinkError> {
+        Ok(Self {
+            client: config.proxy.apply(Client::builder())?.build()?,
+            url: format!("{}{}", config.api_root.trim_end_matches('/'), SUBMIT_LISTENS_PATH),
+            authorization: HeaderValue::from_str(&format!("Token {}", config.token))?,
+            listen_type: config.listen_type,
+        })
+    }
+
+    fn submit(&self, listen_type: ListenType, listen: Listen) -> Result<(), SinkError> {
+        let data = serde_json::to_vec(&Submission {
+            listen_type,
+            payload: [listen],
+        })?;
+        let rep = self
+            .client
+            .post(&self.url)
+            .header(AUTHORIZATION, self.authorization.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(data)
+            .send()?;
+        let status = rep.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        if let Some(delay) = retry_after(status, rep.headers()) {
+            return Err(SinkError::RetryAfter(status, delay));
+        }
+        let body = rep.text()?;
+        match serde_json::from_str::<ErrorResponse>(&body) {
+            Ok(ErrorResponse { error }) => Err(SinkError::ListenBrainz(status, error)),
+            Err(_) => Err(SinkError::RequestFailed(status, response_text(body.as_bytes()))),
+        }
+    }
+}
+
+impl Sink for ListenBrainzSink {
+    fn publish(&mut self, event: &Event) -> Result<(), SinkError> {
+        match event {
+            Event::ConfigChanged(_) => Ok(()),
+            Event::Start(event) => update_now_playing("ListenBrainz", || {
+                self.submit(ListenType::PlayingNow, Listen::new(&event.track_info, None))
+            }),
+            Event::Stop(event) => {
+                if event.is_scrobble() {
+                    self.submit(self.listen_type, Listen::new(&event.track_info, Some(event.started_at)))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Submission<'a> {
+    listen_type: ListenType,
+    payload: [Listen<'a>; 1],
+}
+
+#[derive(Serialize)]
+struct Listen<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listened_at: Option<i64>,
+    track_metadata: TrackMetadata<'a>,
+}
+
+impl<'a> Listen<'a> {
+    fn new(track_info: &'a TrackInfo, listened_at: Option<i64>) -> Self {
+        Self {
+            listened_at,
+            track_metadata: TrackMetadata {
+                artist_name: &track_info.artist,
+                track_name: &track_info.title,
+                release_name: &track_info.album,
+                additional_info: AdditionalInfo {
+                    release_artist_name: track_info.album_artist.as_deref(),
+                    tracknumber: track_info.track_number,
+                    discnumber: track_info.disc_number,
+                    duration_ms: (track_info.duration * 1000.0).round() as u64,
+                    media_player: MEDIA_PLAYER,
+                    submission_client: SUBMISSION_CLIENT,
+                    submission_client_version: SUBMISSION_CLIENT_VERSION,
+                },
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TrackMetadata<'a> {
+    artist_name: &'a str,
+    track_name: &'a str,
+    release_name: &'a str,
+    additional_info: AdditionalInfo<'a>,
+}
+
+#[derive(Serialize)]
+struct AdditionalInfo<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_artist_name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tracknumber: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    discnumber: Option<u32>,
+    duration_ms: u64,
+    media_player: &'static str,
+    submission_client: &'static str,
+    submission_client_version: &'static str,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
@@ -0,0 +1,392 @@
+use crate::{api::ConfigError, config::ConfigSection, event::Event, keys::KeyError};
+use hmac::crypto_mac::InvalidKeyLength;
+use reqwest::{
+    header::{HeaderMap, InvalidHeaderValue, RETRY_AFTER},
+    Error as ReqwestError, StatusCode,
+};
+use rumqttc::ClientError as MqttClientError;
+use rusqlite::Error as SqliteError;
+use serde_json::Error as JsonError;
+use std::{
+    error::Error,
+    fmt,
+    io::Error as IoError,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+mod auth;
+mod jsonl;
+mod lastfm;
+mod listenbrainz;
+mod mqtt;
+mod proxy;
+pub(crate) mod scrobbler_log;
+mod sqlite;
+mod tls;
+mod unix;
+mod webhook;
+
+use self::tls::TlsError;
+
+pub use self::{
+    jsonl::{JsonlConfig, JsonlSink},
+    lastfm::{LastFmConfig, LastFmSink},
+    listenbrainz::{ListenBrainzConfig, ListenBrainzSink},
+    mqtt::{MqttConfig, MqttSink},
+    scrobbler_log::{ScrobblerLogConfig, ScrobblerLogSink},
+    sqlite::{SqliteConfig, SqliteSink},
+    webhook::{WebhookConfig, WebhookSink},
+};
+
+// https://www.last.fm/api/errorcodes, "invalid parameters" and "invalid resource specified"
+const LASTFM_PERMANENT_ERRORS: &[u32] = &[6, 7];
+const MAX_RESPONSE_TEXT_LENGTH: usize = 1000;
+// Misconfigured server should not stop delivery for too long
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub trait Sink: Send {
+    fn publish(&mut self, event: &Event) -> Result<(), SinkError>;
+
+    // Results are in the same order as events, events without a result were not sent.
+    // No more requests are made once stop returns true
+    fn publish_batch(&mut self, events: &[Event], stop: &dyn Fn() -> bool) -> Vec<Result<(), SinkError>> {
+        publish_each(self, events, stop)
+    }
+}
+
+// Now playing notification is useless later, so a failure is only logged and the event is not queued for redelivery
+pub(crate) fn update_now_playing<F>(service: &str, update: F) -> Result<(), SinkError>
+where
+    F: FnOnce() -> Result<(), SinkError>,
+{
+    if let Err(err) = update() {
+        eprintln!("[playlog] Failed to update {} now playing: {}", service, err);
+    }
+    Ok(())
+}
+
+// Sends events one by one until a transient error occurs or publisher has to stop
+pub(crate) fn publish_each<S>(sink: &mut S, events: &[Event], stop: &dyn Fn() -> bool) -> Vec<Result<(), SinkError>>
+where
+    S: Sink + ?Sized,
+{
+    let mut results = Vec::with_capacity(events.len());
+    for event in events {
+        if stop() {
+            break;
+        }
+        let result = sink.publish(event);
+        let failed = matches!(result, Err(ref err) if !err.is_permanent());
+        results.push(result);
+        if failed {
+            break;
+        }
+    }
+    results
+}
+
+// Used in place of a sink which could not be created, every event fails with a transient error
+pub struct UnavailableSink {
+    message: String,
+}
+
+impl UnavailableSink {
+    pub fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Sink for UnavailableSink {
+    fn publish(&mut self, _event: &Event) -> Result<(), SinkError> {
+        Err(SinkError::Unavailable(self.message.clone()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SinkConfig {
+    Jsonl(JsonlConfig),
+    LastFm(LastFmConfig),
+    ListenBrainz(ListenBrainzConfig),
+    Mqtt(MqttConfig),
+    ScrobblerLog(ScrobblerLogConfig),
+    Sqlite(SqliteConfig),
+    Webhook(Box<WebhookConfig>),
+}
+
+impl SinkConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        match section.get_str_opt("type")?.as_deref() {
+            None | Some("webhook") => WebhookConfig::read(section).map(|x| SinkConfig::Webhook(Box::new(x))),
+            Some("jsonl") => JsonlConfig::read(section).map(SinkConfig::Jsonl),
+            Some("lastfm") => LastFmConfig::read(section).map(SinkConfig::LastFm),
+            Some("listenbrainz") => ListenBrainzConfig::read(section).map(SinkConfig::ListenBrainz),
+            Some("mqtt") => MqttConfig::read(section).map(SinkConfig::Mqtt),
+            Some("scrobbler_log") => ScrobblerLogConfig::read(section).map(SinkConfig::ScrobblerLog),
+            Some("sqlite") => SqliteConfig::read(section).map(SinkConfig::Sqlite),
+            Some(value) => Err(ConfigError::InvalidValue(section.key("type"), String::from(value))),
+        }
+    }
+
+    pub fn build(&self, name: &str, data_dir: &Path) -> Result<Box<dyn Sink>, SinkError> {
+        Ok(match self {
+            SinkConfig::Jsonl(config) => Box::new(JsonlSink::new(config, name, data_dir)?),
+            SinkConfig::LastFm(config) => {
+                let session_path = data_dir.join("lastfm").join(format!("{}.session", name));
+                Box::new(LastFmSink::new(config, session_path)?)
+            }
+            SinkConfig::ListenBrainz(config) => Box::new(ListenBrainzSink::new(config)?),
+            SinkConfig::Mqtt(config) => Box::new(MqttSink::new(config, name, data_dir)),
+            SinkConfig::ScrobblerLog(config) => Box::new(ScrobblerLogSink::new(config, name, data_dir)?),
+            SinkConfig::Sqlite(config) => Box::new(SqliteSink::new(config, data_dir)?),
+            SinkConfig::Webhook(config) => Box::new(WebhookSink::new(config, data_dir)?),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum SinkError {
+    InvalidHeaderValue(InvalidHeaderValue),
+    InvalidKeyLength(InvalidKeyLength),
+    InvalidResponse(String),
+    Io(IoError),
+    Json(JsonError),
+    Key(KeyError),
+    LastFm(u32, String),
+    ListenBrainz(StatusCode, String),
+    Mqtt(MqttClientError),
+    MqttDisconnected,
+    OAuth2(StatusCode, String),
+    Reqwest(ReqwestError),
+    RequestFailed(StatusCode, String),
+    RetryAfter(StatusCode, Duration),
+    Sqlite(SqliteError),
+    SqliteSchemaVersion(i32),
+    Tls(TlsError),
+    Unavailable(String),
+}
+
+impl SinkError {
+    // Permanent errors are caused by the event itself, so sending it again is useless
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            // Responses are parsed into InvalidResponse, so Json means the event could not be serialized
+            SinkError::Json(_) => true,
+            SinkError::LastFm(code, _) => LASTFM_PERMANENT_ERRORS.contains(code),
+            // Authentication failures are fixed in config or on server, not in the event
+            SinkError::ListenBrainz(status, _) | SinkError::RequestFailed(status, _) => {
+                status.is_client_error()
+                    && *status != StatusCode::UNAUTHORIZED
+                    && *status != StatusCode::FORBIDDEN
+                    && *status != StatusCode::REQUEST_TIMEOUT
+                    && *status != StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+
+    // Time requested by a server to wait before any next request
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            SinkError::RetryAfter(_, delay) => Some(*delay),
+            _ => None,
+        }
+    }
+}
+
+// Returns delay from Retry-After header (seconds or HTTP date) of 429 and 503 responses
+pub(crate) fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value).ok()?;
+            // Date in the past means that request can be sent right now
+            date.duration_since(SystemTime::now()).unwrap_or_default()
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+pub(crate) fn response_text(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body);
+    let text = text.trim();
+    match text.char_indices().nth(MAX_RESPONSE_TEXT_LENGTH) {
+        Some((idx, _)) => format!("{}...", &text[..idx]),
+        None => String::from(text),
+    }
+}
+
+impl From<InvalidHeaderValue> for SinkError {
+    fn from(err: InvalidHeaderValue) -> Self {
+        SinkError::InvalidHeaderValue(err)
+    }
+}
+
+impl From<InvalidKeyLength> for SinkError {
+    fn from(err: InvalidKeyLength) -> Self {
+        SinkError::InvalidKeyLength(err)
+    }
+}
+
+impl From<IoError> for SinkError {
+    fn from(err: IoError) -> Self {
+        SinkError::Io(err)
+    }
+}
+
+impl From<JsonError> for SinkError {
+    fn from(err: JsonError) -> Self {
+        SinkError::Json(err)
+    }
+}
+
+impl From<KeyError> for SinkError {
+    fn from(err: KeyError) -> Self {
+        SinkError::Key(err)
+    }
+}
+
+impl From<MqttClientError> for SinkError {
+    fn from(err: MqttClientError) -> Self {
+        SinkError::Mqtt(err)
+    }
+}
+
+impl From<ReqwestError> for SinkError {
+    fn from(err: ReqwestError) -> Self {
+        SinkError::Reqwest(err)
+    }
+}
+
+impl From<SqliteError> for SinkError {
+    fn from(err: SqliteError) -> Self {
+        SinkError::Sqlite(err)
+    }
+}
+
+impl From<TlsError> for SinkError {
+    fn from(err: TlsError) -> Self {
+        SinkError::Tls(err)
+    }
+}
+
+impl Error for SinkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SinkError::InvalidHeaderValue(err) => Some(err),
+            SinkError::InvalidKeyLength(_) => None,
+            SinkError::InvalidResponse(_) => None,
+            SinkError::Io(err) => Some(err),
+            SinkError::Json(err) => Some(err),
+            SinkError::Key(err) => Some(err),
+            SinkError::LastFm(_, _) => None,
+            SinkError::ListenBrainz(_, _) => None,
+            SinkError::Mqtt(err) => Some(err),
+            SinkError::MqttDisconnected => None,
+            SinkError::OAuth2(_, _) => None,
+            SinkError::Reqwest(err) => Some(err),
+            SinkError::RequestFailed(_, _) => None,
+            SinkError::RetryAfter(_, _) => None,
+            SinkError::Sqlite(err) => Some(err),
+            SinkError::SqliteSchemaVersion(_) => None,
+            SinkError::Tls(err) => Some(err),
+            SinkError::Unavailable(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SinkError::InvalidHeaderValue(err) => write!(out, "could not set request header: {}", err),
+            SinkError::InvalidKeyLength(err) => write!(out, "secret key error: {}", err),
+            SinkError::InvalidResponse(message) => write!(out, "invalid HTTP response: {}", message),
+            SinkError::Io(err) => write!(out, "IO error: {}", err),
+            SinkError::Json(err) => write!(out, "can not serialize JSON: {}", err),
+            SinkError::Key(err) => write!(out, "{}", err),
+            SinkError::LastFm(code, message) => write!(out, "Last.fm API error {}: {}", code, message),
+            SinkError::ListenBrainz(status, message) => write!(out, "ListenBrainz API error {}: {}", status, message),
+            SinkError::Mqtt(err) => write!(out, "MQTT client error: {}", err),
+            SinkError::MqttDisconnected => write!(out, "MQTT broker is not connected"),
+            SinkError::OAuth2(status, message) => {
+                write!(out, "OAuth2 token request failed with {}: {}", status, message)
+            }
+            SinkError::Reqwest(err) => write!(out, "failed to send HTTP request: {}", err),
+            SinkError::RequestFailed(status, body) => {
+                if body.is_empty() {
+                    write!(out, "server respond with {} status code", status)
+                } else {
+                    write!(out, "server respond with {} status code: {}", status, body)
+                }
+            }
+            SinkError::RetryAfter(status, delay) => write!(
+                out,
+                "server respond with {} status code, retry after {}s",
+                status,
+                delay.as_secs()
+            ),
+            SinkError::Sqlite(err) => write!(out, "SQLite error: {}", err),
+            SinkError::SqliteSchemaVersion(version) => {
+                write!(out, "database schema version {} is not supported", version)
+            }
+            SinkError::Tls(err) => write!(out, "{}", err),
+            SinkError::Unavailable(message) => write!(out, "sink is not available: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        let delay = retry_after(StatusCode::TOO_MANY_REQUESTS, &headers(" 120 "));
+        assert_eq!(delay, Some(Duration::from_secs(120)));
+        let delay = retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers("0"));
+        assert_eq!(delay, Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn retry_after_date() {
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(600));
+        let delay = retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers(&date)).unwrap();
+        // HTTP date has a one second precision
+        assert!(delay > Duration::from_secs(590) && delay <= Duration::from_secs(600));
+    }
+
+    #[test]
+    fn retry_after_past_date() {
+        let date = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(600));
+        let delay = retry_after(StatusCode::TOO_MANY_REQUESTS, &headers(&date));
+        assert_eq!(delay, Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn retry_after_clamped() {
+        let delay = retry_after(StatusCode::TOO_MANY_REQUESTS, &headers("18446744073709551615"));
+        assert_eq!(delay, Some(MAX_RETRY_AFTER));
+        let date = httpdate::fmt_http_date(SystemTime::now() + MAX_RETRY_AFTER * 2);
+        let delay = retry_after(StatusCode::TOO_MANY_REQUESTS, &headers(&date));
+        assert_eq!(delay, Some(MAX_RETRY_AFTER));
+    }
+
+    #[test]
+    fn retry_after_ignored() {
+        assert_eq!(retry_after(StatusCode::INTERNAL_SERVER_ERROR, &headers("10")), None);
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new()), None);
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &headers("soon")), None);
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &headers("-5")), None);
+    }
+}
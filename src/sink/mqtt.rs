@@ -0,0 +1,203 @@
+use crate::{
+    api::ConfigError,
+    config::ConfigSection,
+    event::Event,
+    sink::{Sink, SinkError},
+};
+use rumqttc::{Client, Connection, Event as MqttEvent, MqttOptions, Outgoing, Packet, QoS, RecvTimeoutError};
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_PORT: i32 = 1883;
+const DEFAULT_QOS: i32 = 1;
+const DEFAULT_KEEP_ALIVE: i32 = 30;
+const DEFAULT_TOPIC_START: &str = "deadbeef/playlog/start";
+const DEFAULT_TOPIC_STOP: &str = "deadbeef/playlog/stop";
+const DEFAULT_TOPIC_NOW_PLAYING: &str = "deadbeef/playlog/now_playing";
+const DEVICE_ID_FILE: &str = "mqtt_device_id";
+const REQUESTS_CAPACITY: usize = 16;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// How often connection thread checks whether the sink is dropped
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MqttConfig {
+    host: String,
+    port: u16,
+    client_id: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    qos: QoS,
+    keep_alive: Duration,
+    topic_start: String,
+    topic_stop: String,
+    topic_now_playing: String,
+}
+
+impl MqttConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        macro_rules! topic {
+            ($key:expr, $default:expr) => {
+                section
+                    .get_str_opt($key)?
+                    .unwrap_or_else(|| String::from($default))
+            };
+        }
+        let port = section.get_int("port", DEFAULT_PORT)?;
+        let qos = section.get_int("qos", DEFAULT_QOS)?;
+        Ok(Self {
+            host: section.get_str("host")?,
+            port: match port {
+                1..=65535 => port as u16,
+                _ => return Err(ConfigError::InvalidValue(section.key("port"), port.to_string())),
+            },
+            client_id: section.get_str_opt("client_id")?,
+            username: section.get_str_opt("username")?,
+            password: section.get_str_opt("password")?,
+            qos: match qos {
+                0 => QoS::AtMostOnce,
+                1 => QoS::AtLeastOnce,
+                2 => QoS::ExactlyOnce,
+                _ => return Err(ConfigError::InvalidValue(section.key("qos"), qos.to_string())),
+            },
+            keep_alive: Duration::from_secs(section.get_int("keep_alive", DEFAULT_KEEP_ALIVE)?.max(5) as u64),
+            topic_start: topic!("topic_start", DEFAULT_TOPIC_START),
+            topic_stop: topic!("topic_stop", DEFAULT_TOPIC_STOP),
+            topic_now_playing: topic!("topic_now_playing", DEFAULT_TOPIC_NOW_PLAYING),
+        })
+    }
+}
+
+pub struct MqttSink {
+    client: Client,
+    connected: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    connection: Option<JoinHandle<()>>,
+    qos: QoS,
+    topic_start: String,
+    topic_stop: String,
+    topic_now_playing: String,
+}
+
+impl MqttSink {
+    pub fn new(config: &MqttConfig, name: &str, data_dir: &Path) -> Self {
+        let client_id = match config.client_id {
+            Some(ref client_id) => client_id.clone(),
+            // Broker disconnects a client when another one connects with the same ID
+            None => format!("deadbeef-playlog-{}-{}", name, device_id(data_dir)),
+        };
+        let mut options = MqttOptions::new(client_id, config.host.clone(), config.port);
+        options.set_keep_alive(config.keep_alive);
+        if let Some(ref username) = config.username {
+            options.set_credentials(username.clone(), config.password.clone().unwrap_or_default());
+        }
+        let (client, connection) = Client::new(options, REQUESTS_CAPACITY);
+        let connected = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let connection = {
+            let connected = connected.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || run_connection(connection, connected, stopped))
+        };
+        Self {
+            client,
+            connected,
+            stopped,
+            connection: Some(connection),
+            qos: config.qos,
+            topic_start: config.topic_start.clone(),
+            topic_stop: config.topic_stop.clone(),
+            topic_now_playing: config.topic_now_playing.clone(),
+        }
+    }
+
+    fn send(&mut self, topic: &str, retain: bool, payload: Vec<u8>) -> Result<(), SinkError> {
+        if !self.connected.load(Ordering::SeqCst) {
+            return Err(SinkError::MqttDisconnected);
+        }
+        self.client.try_publish(topic, self.qos, retain, payload)?;
+        Ok(())
+    }
+}
+
+// Random ID which is generated once and kept in data directory
+fn device_id(data_dir: &Path) -> String {
+    let path = data_dir.join(DEVICE_ID_FILE);
+    match fs::read_to_string(&path) {
+        Ok(id) if !id.trim().is_empty() => return String::from(id.trim()),
+        _ => {}
+    }
+    let id = hex::encode(rand::random::<[u8; 8]>());
+    if let Err(err) = fs::create_dir_all(data_dir).and_then(|()| fs::write(&path, &id)) {
+        eprintln!(
+            "[playlog] Failed to save MQTT device ID to {}: {}, a new one is used on next start",
+            path.display(),
+            err
+        );
+    }
+    id
+}
+
+impl Sink for MqttSink {
+    fn publish(&mut self, event: &Event) -> Result<(), SinkError> {
+        let data = serde_json::to_vec(&event)?;
+        let (topic, now_playing) = match event {
+            Event::ConfigChanged(_) => return Ok(()),
+            Event::Start(_) => (self.topic_start.clone(), data.clone()),
+            // Empty retained message removes retained now playing message
+            Event::Stop(_) => (self.topic_stop.clone(), Vec::new()),
+        };
+        self.send(&topic, false, data)?;
+        let topic_now_playing = self.topic_now_playing.clone();
+        if let Err(err) = self.send(&topic_now_playing, true, now_playing) {
+            eprintln!("[playlog] Failed to update MQTT now playing topic: {}", err);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        // Disconnect request never reaches an unreachable broker, so the thread checks the flag as well
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = self.client.try_disconnect();
+        if let Some(connection) = self.connection.take() {
+            if let Err(err) = connection.join() {
+                eprintln!(
+                    "[playlog] an error has occurred when joining MQTT connection thread: {:?}",
+                    err
+                );
+            }
+        }
+    }
+}
+
+fn run_connection(mut connection: Connection, connected: Arc<AtomicBool>, stopped: Arc<AtomicBool>) {
+    while !stopped.load(Ordering::SeqCst) {
+        match connection.recv_timeout(STOP_CHECK_INTERVAL) {
+            Ok(Ok(MqttEvent::Incoming(Packet::ConnAck(_)))) => connected.store(true, Ordering::SeqCst),
+            Ok(Ok(MqttEvent::Outgoing(Outgoing::Disconnect))) => break,
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(err)) => {
+                if connected.swap(false, Ordering::SeqCst) {
+                    eprintln!("[playlog] MQTT connection error: {}", err);
+                }
+                let reconnect_at = Instant::now() + RECONNECT_DELAY;
+                while !stopped.load(Ordering::SeqCst) && Instant::now() < reconnect_at {
+                    sleep(STOP_CHECK_INTERVAL);
+                }
+            }
+            // Requests channel is closed
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    connected.store(false, Ordering::SeqCst);
+}
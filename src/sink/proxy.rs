@@ -0,0 +1,64 @@
+use crate::{api::ConfigError, config::ConfigSection, sink::SinkError};
+use reqwest::{blocking::ClientBuilder, Proxy, Url};
+
+const SCHEMES: &[&str] = &["http", "https", "socks5", "socks5h"];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProxyConfig {
+    url: Option<Url>,
+    no_proxy: Vec<String>,
+}
+
+impl ProxyConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        let value = match section.get_str_opt("proxy")? {
+            Some(value) => value,
+            None => return Ok(Self::default()),
+        };
+        let invalid = || ConfigError::InvalidValue(section.key("proxy"), value.clone());
+        let mut url = Url::parse(&value).map_err(|_| invalid())?;
+        if !SCHEMES.contains(&url.scheme()) || url.host_str().is_none() {
+            return Err(invalid());
+        }
+        // Credentials are passed in URL, so they work for SOCKS5 as well as for HTTP proxies
+        if let Some(username) = section.get_str_opt("proxy.username")? {
+            let password = section.get_str("proxy.password")?;
+            url.set_username(&username).map_err(|()| invalid())?;
+            url.set_password(Some(&password)).map_err(|()| invalid())?;
+        }
+        let no_proxy = section
+            .get_str_opt("no_proxy")?
+            .unwrap_or_default()
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.trim_start_matches('.').to_lowercase())
+            .collect();
+        Ok(Self {
+            url: Some(url),
+            no_proxy,
+        })
+    }
+
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, SinkError> {
+        let url = match self.url {
+            Some(ref url) => url.clone(),
+            None => return Ok(builder),
+        };
+        if self.no_proxy.is_empty() {
+            return Ok(builder.proxy(Proxy::all(url)?));
+        }
+        let no_proxy = self.no_proxy.clone();
+        Ok(builder.proxy(Proxy::custom(move |target| {
+            let host = target.host_str()?.to_lowercase();
+            if no_proxy.iter().any(|x| is_excluded(x, &host)) {
+                None
+            } else {
+                Some(url.clone())
+            }
+        })))
+    }
+}
+
+fn is_excluded(pattern: &str, host: &str) -> bool {
+    pattern == "*" || host == pattern || (host.ends_with(pattern) && host[..host.len() - pattern.len()].ends_with('.'))
+}
@@ -0,0 +1,101 @@
+use crate::{
+    api::ConfigError,
+    config::ConfigSection,
+    event::{Event, EventStop},
+    sink::{Sink, SinkError},
+};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+// https://web.archive.org/web/20170107015006/http://www.audioscrobbler.net/wiki/Portable_Player_Logging
+const HEADER_VERSION: &str = "#AUDIOSCROBBLER/1.1";
+const HEADER_CLIENT: &str = concat!("#CLIENT/deadbeef-playlog ", env!("CARGO_PKG_VERSION"));
+pub(crate) const HEADER_TZ_UTC: &str = "#TZ/UTC";
+pub(crate) const HEADER_TZ_UNKNOWN: &str = "#TZ/UNKNOWN";
+pub(crate) const RATING_LISTENED: &str = "L";
+pub(crate) const RATING_SKIPPED: &str = "S";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScrobblerLogConfig {
+    path: Option<PathBuf>,
+}
+
+impl ScrobblerLogConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        Ok(Self {
+            path: section.get_str_opt("path")?.map(PathBuf::from),
+        })
+    }
+}
+
+pub struct ScrobblerLogSink {
+    path: PathBuf,
+}
+
+impl ScrobblerLogSink {
+    pub fn new(config: &ScrobblerLogConfig, name: &str, data_dir: &Path) -> Result<Self, SinkError> {
+        let path = match config.path {
+            Some(ref path) => path.clone(),
+            None => data_dir.join(format!("{}.scrobbler.log", name)),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(Self { path })
+    }
+
+    fn write(&self, event: &EventStop) -> Result<(), SinkError> {
+        let mut file = OpenOptions::new().append(true).create(true).open(&self.path)?;
+        let mut data = String::new();
+        if file.metadata()?.len() == 0 {
+            for header in &[HEADER_VERSION, HEADER_TZ_UTC, HEADER_CLIENT] {
+                data.push_str(header);
+                data.push('\n');
+            }
+        }
+        data.push_str(&format_line(event));
+        file.write_all(data.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Sink for ScrobblerLogSink {
+    fn publish(&mut self, event: &Event) -> Result<(), SinkError> {
+        match event {
+            Event::Stop(event) => self.write(event),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn format_line(event: &EventStop) -> String {
+    let track_info = &event.track_info;
+    let fields = [
+        clean(&track_info.artist),
+        clean(&track_info.album),
+        clean(&track_info.title),
+        track_info.track_number.map(|x| x.to_string()).unwrap_or_default(),
+        (track_info.duration.round() as u32).to_string(),
+        String::from(if event.is_scrobble() {
+            RATING_LISTENED
+        } else {
+            RATING_SKIPPED
+        }),
+        event.started_at.to_string(),
+        track_info
+            .musicbrainz_track_id
+            .as_deref()
+            .map(clean)
+            .unwrap_or_default(),
+    ];
+    let mut line = fields.join("\t");
+    line.push('\n');
+    line
+}
+
+fn clean(value: &str) -> String {
+    value.replace(&['\t', '\n', '\r'][..], " ")
+}
@@ -0,0 +1,154 @@
+use crate::{
+    api::{ConfigError, TrackInfo},
+    config::ConfigSection,
+    event::Event,
+    sink::{Sink, SinkError},
+};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SCHEMA_VERSION: i32 = 1;
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS tracks (
+    id INTEGER PRIMARY KEY,
+    artist TEXT NOT NULL,
+    album_artist TEXT,
+    album TEXT NOT NULL,
+    title TEXT NOT NULL,
+    year INTEGER,
+    disc_number INTEGER,
+    total_discs INTEGER,
+    track_number INTEGER,
+    total_tracks INTEGER,
+    duration REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS tracks_title ON tracks (artist, album, title);
+CREATE TABLE IF NOT EXISTS plays (
+    id INTEGER PRIMARY KEY,
+    track_id INTEGER NOT NULL REFERENCES tracks (id),
+    event TEXT NOT NULL CHECK (event IN ('start', 'stop')),
+    started_at INTEGER NOT NULL,
+    play_time REAL,
+    recorded_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS plays_started_at ON plays (started_at);
+"#;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SqliteConfig {
+    path: Option<PathBuf>,
+}
+
+impl SqliteConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        Ok(Self {
+            path: section.get_str_opt("path")?.map(PathBuf::from),
+        })
+    }
+}
+
+pub struct SqliteSink {
+    connection: Connection,
+}
+
+impl SqliteSink {
+    pub fn new(config: &SqliteConfig, data_dir: &Path) -> Result<Self, SinkError> {
+        let path = match config.path {
+            Some(ref path) => path.clone(),
+            None => data_dir.join("history.sqlite"),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let connection = Connection::open(path)?;
+        let version: i32 = connection.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(SinkError::SqliteSchemaVersion(version));
+        }
+        connection.execute_batch(SCHEMA)?;
+        connection.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+        Ok(Self { connection })
+    }
+
+    fn insert_play(
+        &mut self,
+        track_info: &TrackInfo,
+        event: &str,
+        started_at: i64,
+        play_time: Option<f32>,
+    ) -> Result<(), SinkError> {
+        let recorded_at = now();
+        let tx = self.connection.transaction()?;
+        let track_id = find_track(&tx, track_info)?;
+        let track_id = match track_id {
+            Some(track_id) => track_id,
+            None => {
+                tx.execute(
+                    "INSERT INTO tracks (
+                        artist, album_artist, album, title, year,
+                        disc_number, total_discs, track_number, total_tracks, duration
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        track_info.artist,
+                        track_info.album_artist,
+                        track_info.album,
+                        track_info.title,
+                        track_info.year,
+                        track_info.disc_number,
+                        track_info.total_discs,
+                        track_info.track_number,
+                        track_info.total_tracks,
+                        f64::from(track_info.duration),
+                    ],
+                )?;
+                tx.last_insert_rowid()
+            }
+        };
+        tx.execute(
+            "INSERT INTO plays (track_id, event, started_at, play_time, recorded_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![track_id, event, started_at, play_time.map(f64::from), recorded_at],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl Sink for SqliteSink {
+    fn publish(&mut self, event: &Event) -> Result<(), SinkError> {
+        match event {
+            Event::ConfigChanged(_) => Ok(()),
+            Event::Start(event) => self.insert_play(&event.track_info, "start", event.started_at, None),
+            Event::Stop(event) => self.insert_play(&event.track_info, "stop", event.started_at, Some(event.play_time)),
+        }
+    }
+}
+
+fn find_track(connection: &Connection, track_info: &TrackInfo) -> Result<Option<i64>, SinkError> {
+    Ok(connection
+        .query_row(
+            "SELECT id FROM tracks
+            WHERE artist = ?1 AND album_artist IS ?2 AND album = ?3 AND title = ?4
+            AND disc_number IS ?5 AND track_number IS ?6",
+            params![
+                track_info.artist,
+                track_info.album_artist,
+                track_info.album,
+                track_info.title,
+                track_info.disc_number,
+                track_info.track_number,
+            ],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,289 @@
+use crate::{api::ConfigError, config::ConfigSection};
+use reqwest::blocking::ClientBuilder;
+use rustls::{
+    internal::pemfile, Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
+    WebPKIVerifier,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{BufReader, Error as IoError},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use webpki::DNSNameRef;
+
+const PIN_PREFIX: &str = "sha256//";
+const PIN_LENGTH: usize = 32;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TlsConfig {
+    ca_bundle: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    pins: Vec<Vec<u8>>,
+}
+
+impl TlsConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        let mut pins = Vec::new();
+        if let Some(value) = section.get_str_opt("tls.pin_sha256")? {
+            for pin in value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|x| !x.is_empty())
+            {
+                let pin = pin.trim_start_matches(PIN_PREFIX);
+                match base64::decode(pin) {
+                    Ok(pin) if pin.len() == PIN_LENGTH => pins.push(pin),
+                    _ => {
+                        return Err(ConfigError::InvalidValue(
+                            section.key("tls.pin_sha256"),
+                            String::from(pin),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            ca_bundle: section.get_str_opt("tls.ca_bundle")?.map(PathBuf::from),
+            client_cert: section.get_str_opt("tls.client_cert")?.map(PathBuf::from),
+            client_key: section.get_str_opt("tls.client_key")?.map(PathBuf::from),
+            pins,
+        })
+    }
+
+    // Pins and client certificate are meant for the endpoint only, not for other hosts it talks to
+    pub fn without_identity(&self) -> Self {
+        Self {
+            ca_bundle: self.ca_bundle.clone(),
+            ..Self::default()
+        }
+    }
+
+    pub fn apply(&self, builder: ClientBuilder) -> Result<ClientBuilder, TlsError> {
+        // Default config of reqwest (rustls with system root certificates) is kept unless some of options are set
+        if *self == TlsConfig::default() {
+            return Ok(builder);
+        }
+        let mut config = ClientConfig::new();
+        config.root_store = match rustls_native_certs::load_native_certs() {
+            Ok(store) => store,
+            Err((Some(store), err)) => {
+                eprintln!(
+                    "[playlog] Some of system root certificates could not be loaded: {}",
+                    err
+                );
+                store
+            }
+            Err((None, err)) => return Err(TlsError::NativeCerts(err)),
+        };
+        if let Some(ref path) = self.ca_bundle {
+            let mut reader = open(path)?;
+            match config.root_store.add_pem_file(&mut reader) {
+                Ok((valid, _)) if valid > 0 => {}
+                _ => return Err(TlsError::InvalidPem(path.clone())),
+            }
+        }
+        if let Some(ref cert_path) = self.client_cert {
+            // Key may be stored in the same file with certificate
+            let key_path = self.client_key.as_ref().unwrap_or(cert_path);
+            let certs = pemfile::certs(&mut open(cert_path)?).map_err(|()| TlsError::InvalidPem(cert_path.clone()))?;
+            if certs.is_empty() {
+                return Err(TlsError::InvalidPem(cert_path.clone()));
+            }
+            let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?)
+                .map_err(|()| TlsError::InvalidPem(key_path.clone()))?;
+            if keys.is_empty() {
+                keys = pemfile::rsa_private_keys(&mut open(key_path)?)
+                    .map_err(|()| TlsError::InvalidPem(key_path.clone()))?;
+            }
+            let key = match keys.into_iter().next() {
+                Some(key) => key,
+                None => return Err(TlsError::NoPrivateKey(key_path.clone())),
+            };
+            config.set_single_client_cert(certs, key)?;
+        }
+        if !self.pins.is_empty() {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedCertVerifier {
+                    inner: WebPKIVerifier::new(),
+                    pins: self.pins.clone(),
+                }));
+        }
+        Ok(builder.use_preconfigured_tls(config))
+    }
+}
+
+struct PinnedCertVerifier {
+    inner: WebPKIVerifier,
+    pins: Vec<Vec<u8>>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        // Pinning is applied on top of the usual chain verification, not instead of it
+        let verified = self
+            .inner
+            .verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+        for cert in presented_certs {
+            if let Some(spki) = subject_public_key_info(&cert.0) {
+                let hash = Sha256::digest(spki);
+                if self.pins.iter().any(|pin| pin[..] == hash[..]) {
+                    return Ok(verified);
+                }
+            }
+        }
+        Err(TLSError::General(String::from(
+            "server certificate does not match any of pinned public keys",
+        )))
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))
+}
+
+// Returns DER encoded SubjectPublicKeyInfo of X.509 certificate
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = der_next(cert)?;
+    let (_, tbs, _) = der_next(cert)?;
+    let mut data = tbs;
+    // Version is optional and tagged with [0]
+    let (tag, _, rest) = der_next(data)?;
+    if tag == 0xa0 {
+        data = rest;
+    }
+    // serialNumber, signature, issuer, validity and subject
+    for _ in 0..5 {
+        data = der_next(data)?.2;
+    }
+    let (_, _, rest) = der_next(data)?;
+    Some(&data[..data.len() - rest.len()])
+}
+
+// Returns tag, content and remaining data of a DER value
+fn der_next(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&len, mut data) = data.split_first()?;
+    let len = if len & 0x80 == 0 {
+        len as usize
+    } else {
+        let size = (len & 0x7f) as usize;
+        if size == 0 || size > 4 || data.len() < size {
+            return None;
+        }
+        let (len, rest) = data.split_at(size);
+        data = rest;
+        len.iter().fold(0, |acc, x| (acc << 8) | *x as usize)
+    };
+    if data.len() < len {
+        return None;
+    }
+    let (content, rest) = data.split_at(len);
+    Some((tag, content, rest))
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    InvalidPem(PathBuf),
+    Io(PathBuf, IoError),
+    NativeCerts(IoError),
+    NoPrivateKey(PathBuf),
+    Rustls(TLSError),
+}
+
+impl From<TLSError> for TlsError {
+    fn from(err: TLSError) -> Self {
+        TlsError::Rustls(err)
+    }
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        use self::TlsError::*;
+        match self {
+            InvalidPem(_) => None,
+            Io(_, err) => Some(err),
+            NativeCerts(err) => Some(err),
+            NoPrivateKey(_) => None,
+            Rustls(err) => Some(err),
+        }
+    }
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        use self::TlsError::*;
+        match self {
+            InvalidPem(path) => write!(out, "no valid PEM certificates found in {}", path.display()),
+            Io(path, err) => write!(out, "can not read {}: {}", path.display(), err),
+            NativeCerts(err) => write!(out, "can not load system root certificates: {}", err),
+            NoPrivateKey(path) => write!(out, "no private key found in {}", path.display()),
+            Rustls(err) => write!(out, "TLS error: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(pem: &str) -> Vec<u8> {
+        let mut certs = pemfile::certs(&mut pem.as_bytes()).unwrap();
+        certs.remove(0).0
+    }
+
+    // Expected pins are computed with openssl x509 -pubkey | openssl pkey -pubin -outform der | sha256
+    fn pin(cert: &[u8]) -> String {
+        base64::encode(Sha256::digest(subject_public_key_info(cert).unwrap()))
+    }
+
+    #[test]
+    fn spki_of_ec_certificate() {
+        let cert = load(include_str!("testdata/ec.pem"));
+        assert_eq!(pin(&cert), "DvBDvkbZ2YWW5YxGAk/R8B9LcYpc3soTYeLAcY1Y3p4=");
+    }
+
+    #[test]
+    fn spki_of_rsa_certificate() {
+        // Values longer than 255 bytes use two length bytes
+        let cert = load(include_str!("testdata/rsa.pem"));
+        assert_eq!(pin(&cert), "pPtWUNBDm0kw9VWqRAu8v0RVFpX5dJEywb7JvtJeZcY=");
+    }
+
+    #[test]
+    fn spki_of_truncated_certificate() {
+        let cert = load(include_str!("testdata/ec.pem"));
+        for len in 0..cert.len() {
+            assert_eq!(subject_public_key_info(&cert[..len]), None);
+        }
+    }
+
+    #[test]
+    fn der_lengths() {
+        assert_eq!(
+            der_next(&[0x04, 0x01, 0xaa, 0xbb]),
+            Some((0x04, &[0xaa][..], &[0xbb][..]))
+        );
+        assert_eq!(der_next(&[0x04, 0x81, 0x01, 0xaa]), Some((0x04, &[0xaa][..], &[][..])));
+        assert_eq!(
+            der_next(&[0x04, 0x82, 0x00, 0x01, 0xaa]),
+            Some((0x04, &[0xaa][..], &[][..]))
+        );
+        // Indefinite and too long lengths are not valid DER
+        assert_eq!(der_next(&[0x04, 0x80, 0xaa]), None);
+        assert_eq!(der_next(&[0x04, 0x85, 0, 0, 0, 0, 1, 0xaa]), None);
+        assert_eq!(der_next(&[0x04, 0x02, 0xaa]), None);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::sink::SinkError;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::Duration,
+};
+
+// Same as default timeout of reqwest client
+const TIMEOUT: Duration = Duration::from_secs(30);
+// Misbehaving server should not be able to exhaust memory
+const MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+// Minimal HTTP/1.1 client, a new connection is used for every request
+pub fn post(socket: &Path, path: &str, headers: &HeaderMap, body: &[u8]) -> Result<Response, SinkError> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut req = Vec::with_capacity(body.len() + 512);
+    write!(
+        req,
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+        path,
+        body.len()
+    )?;
+    for (name, value) in headers {
+        req.extend_from_slice(name.as_str().as_bytes());
+        req.extend_from_slice(b": ");
+        req.extend_from_slice(value.as_bytes());
+        req.extend_from_slice(b"\r\n");
+    }
+    req.extend_from_slice(b"\r\n");
+    req.extend_from_slice(body);
+    stream.write_all(&req)?;
+    read_response(&mut BufReader::new(stream))
+}
+
+fn read_response<R: BufRead>(reader: &mut R) -> Result<Response, SinkError> {
+    loop {
+        let line = read_line(reader)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|x| x.parse().ok())
+            .and_then(|x| StatusCode::from_u16(x).ok())
+            .ok_or_else(|| invalid(format!("bad status line: {}", line)))?;
+        let headers = read_headers(reader)?;
+        // Interim responses (e.g. 100 Continue) are followed by the final one
+        if status.is_informational() {
+            continue;
+        }
+        let chunked = headers.get_all("transfer-encoding").iter().any(|x| {
+            x.to_str()
+                .map(|x| x.to_lowercase().contains("chunked"))
+                .unwrap_or(false)
+        });
+        let body = if chunked {
+            read_chunked(reader)?
+        } else {
+            match headers.get("content-length") {
+                Some(value) => {
+                    let len = value
+                        .to_str()
+                        .ok()
+                        .and_then(|x| x.trim().parse::<usize>().ok())
+                        .ok_or_else(|| invalid(String::from("bad content length")))?;
+                    if len > MAX_RESPONSE_SIZE {
+                        return Err(too_large());
+                    }
+                    let mut body = Vec::with_capacity(len);
+                    read_exact(reader, len, &mut body)?;
+                    body
+                }
+                None => {
+                    let mut body = Vec::new();
+                    reader.take(MAX_RESPONSE_SIZE as u64 + 1).read_to_end(&mut body)?;
+                    if body.len() > MAX_RESPONSE_SIZE {
+                        return Err(too_large());
+                    }
+                    body
+                }
+            }
+        };
+        return Ok(Response { status, headers, body });
+    }
+}
+
+fn read_headers<R: BufRead>(reader: &mut R) -> Result<HeaderMap, SinkError> {
+    let mut headers = HeaderMap::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() >= MAX_HEADERS {
+            return Err(invalid(String::from("too many headers")));
+        }
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or_default().trim();
+        let value = parts
+            .next()
+            .ok_or_else(|| invalid(format!("bad header: {}", line)))?
+            .trim();
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid(format!("bad header: {}", line)))?;
+        headers.append(name, HeaderValue::from_str(value)?);
+    }
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, SinkError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid(format!("bad chunk size: {}", line)))?;
+        if size == 0 {
+            // Trailer headers are not used
+            read_headers(reader)?;
+            return Ok(body);
+        }
+        match body.len().checked_add(size) {
+            Some(len) if len <= MAX_RESPONSE_SIZE => {}
+            _ => return Err(too_large()),
+        }
+        read_exact(reader, size, &mut body)?;
+        if !read_line(reader)?.is_empty() {
+            return Err(invalid(String::from("chunk is not terminated with CRLF")));
+        }
+    }
+}
+
+// Appends exactly len bytes to buffer, memory is allocated as data arrives
+fn read_exact<R: Read>(reader: &mut R, len: usize, buf: &mut Vec<u8>) -> Result<(), SinkError> {
+    if reader.take(len as u64).read_to_end(buf)? < len {
+        return Err(invalid(String::from("unexpected end of response")));
+    }
+    Ok(())
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, SinkError> {
+    let mut line = String::new();
+    if reader.take(MAX_LINE_LENGTH as u64).read_line(&mut line)? == 0 {
+        return Err(invalid(String::from("unexpected end of response")));
+    }
+    if !line.ends_with('\n') {
+        return Err(invalid(String::from("line is too long or not terminated")));
+    }
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn invalid(message: String) -> SinkError {
+    SinkError::InvalidResponse(message)
+}
+
+fn too_large() -> SinkError {
+    invalid(format!("response is larger than {} bytes", MAX_RESPONSE_SIZE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(data: &str) -> Result<Response, SinkError> {
+        read_response(&mut data.as_bytes())
+    }
+
+    #[test]
+    fn content_length() {
+        let rep = read("HTTP/1.1 201 Created\r\nContent-Length: 5\r\nX-Test: a\r\n\r\nhello, extra").unwrap();
+        assert_eq!(rep.status, StatusCode::CREATED);
+        assert_eq!(rep.headers["x-test"], "a");
+        assert_eq!(rep.body, b"hello");
+    }
+
+    #[test]
+    fn content_length_truncated() {
+        assert!(read("HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello").is_err());
+    }
+
+    #[test]
+    fn content_length_too_large() {
+        assert!(read("HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\n").is_err());
+        assert!(read("HTTP/1.1 200 OK\r\nContent-Length: 99999999999999999999999\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn until_close() {
+        let rep = read("HTTP/1.1 200 OK\r\n\r\nhello").unwrap();
+        assert_eq!(rep.body, b"hello");
+    }
+
+    #[test]
+    fn chunked() {
+        let rep = read(concat!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "5;ext=1\r\nhello\r\n",
+            "7\r\n, world\r\n",
+            "0\r\nX-Trailer: a\r\n\r\n"
+        ))
+        .unwrap();
+        assert_eq!(rep.body, b"hello, world");
+    }
+
+    #[test]
+    fn chunked_invalid() {
+        let head = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        // Missing CRLF after chunk data
+        assert!(read(&format!("{}5\r\nhello!\r\n0\r\n\r\n", head)).is_err());
+        // Size overflow and chunks over the limit
+        assert!(read(&format!("{}ffffffffffffffff\r\n", head)).is_err());
+        assert!(read(&format!("{}1\r\na\r\nffffffffffffffff\r\n", head)).is_err());
+        assert!(read(&format!("{}{:x}\r\n", head, MAX_RESPONSE_SIZE + 1)).is_err());
+    }
+
+    #[test]
+    fn continue_before_final_response() {
+        let rep = read(concat!(
+            "HTTP/1.1 100 Continue\r\n\r\n",
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 5\r\nContent-Length: 2\r\n\r\nno"
+        ))
+        .unwrap();
+        assert_eq!(rep.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(rep.headers["retry-after"], "5");
+        assert_eq!(rep.body, b"no");
+    }
+
+    #[test]
+    fn long_line() {
+        let line = "a".repeat(MAX_LINE_LENGTH + 1);
+        assert!(read(&format!("HTTP/1.1 200 OK\r\nX-Test: {}\r\n\r\n", line)).is_err());
+    }
+}
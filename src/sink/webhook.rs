@@ -0,0 +1,348 @@
+use crate::{
+    api::ConfigError,
+    config::ConfigSection,
+    event::Event,
+    keys::{self, DEFAULT_PRIVATE_KEY},
+    sink::{
+        auth::{Auth, AuthConfig},
+        proxy::ProxyConfig,
+        publish_each, response_text, retry_after,
+        tls::TlsConfig,
+        unix::{self, Response},
+        Sink, SinkError,
+    },
+};
+use ed25519_dalek::{Keypair, Signer as _};
+use hmac::{Hmac, Mac};
+use reqwest::{
+    blocking::Client,
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    StatusCode,
+};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+const HEADER_HMAC_SIGNATURE: &str = "x-hmac-signature";
+const HEADER_ED25519_SIGNATURE: &str = "x-ed25519-signature";
+const HEADER_KEY_ID: &str = "x-playlog-key-id";
+const HEADER_TIMESTAMP: &str = "x-playlog-timestamp";
+const HEADER_NONCE: &str = "x-playlog-nonce";
+const HEADER_IDEMPOTENCY_KEY: &str = "idempotency-key";
+const UNIX_SCHEME: &str = "unix://";
+const DEFAULT_REQUEST_PATH: &str = "/";
+// Queued events are sent one by one unless batch size is set
+const DEFAULT_BATCH_SIZE: i32 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Target {
+    Http(String),
+    Unix(PathBuf, String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SignatureScheme {
+    // HMAC of request body only, kept for existing receivers
+    Body,
+    // Signature of "<timestamp>.<nonce>.<body>", timestamp and nonce are sent in headers
+    Timestamped,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum SigningKeyConfig {
+    // Requests are not signed, e.g. when auth is used instead
+    None,
+    Hmac(String),
+    // Path to a private key, default key from data directory is used when not set
+    Ed25519(Option<PathBuf>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebhookConfig {
+    target: Target,
+    key_id: Option<HeaderValue>,
+    key: SigningKeyConfig,
+    signature: SignatureScheme,
+    auth: AuthConfig,
+    tls: TlsConfig,
+    proxy: ProxyConfig,
+    batch_size: usize,
+}
+
+impl WebhookConfig {
+    pub(crate) unsafe fn read(section: &ConfigSection) -> Result<Self, ConfigError> {
+        let signature = match section.get_str_opt("signature")?.as_deref() {
+            None | Some("body") => SignatureScheme::Body,
+            Some("timestamp") => SignatureScheme::Timestamped,
+            Some(value) => return Err(ConfigError::InvalidValue(section.key("signature"), String::from(value))),
+        };
+        let key_id = section.get_str_opt("key_id")?;
+        let secret = match key_id {
+            // Named secrets allow to keep old and new keys in config during rotation
+            Some(ref key_id) => section.get_str_opt(&format!("secret.{}", key_id))?,
+            None => None,
+        };
+        let key = match section.get_str_opt("algorithm")?.as_deref() {
+            Some("none") => SigningKeyConfig::None,
+            None | Some("hmac-sha256") => SigningKeyConfig::Hmac(match secret {
+                Some(secret) => secret,
+                None => section.get_str("secret")?,
+            }),
+            Some("ed25519") => SigningKeyConfig::Ed25519(section.get_str_opt("private_key")?.map(PathBuf::from)),
+            Some(value) => return Err(ConfigError::InvalidValue(section.key("algorithm"), String::from(value))),
+        };
+        let url = section.get_str("url")?;
+        let target = if let Some(socket) = url.strip_prefix(UNIX_SCHEME) {
+            let request_path = section
+                .get_str_opt("request_path")?
+                .unwrap_or_else(|| String::from(DEFAULT_REQUEST_PATH));
+            if !request_path.starts_with('/') || request_path.contains(char::is_whitespace) {
+                return Err(ConfigError::InvalidValue(section.key("request_path"), request_path));
+            }
+            Target::Unix(PathBuf::from(socket), request_path)
+        } else {
+            Target::Http(url)
+        };
+        let batch_size = section.get_int("batch_size", DEFAULT_BATCH_SIZE)?.max(1);
+        // Server may store later events of a batch before a failed earlier one is redelivered
+        if batch_size > 1 && section.get_str_opt("ordering")?.as_deref() == Some("strict") {
+            return Err(ConfigError::InvalidValue(
+                section.key("batch_size"),
+                batch_size.to_string(),
+            ));
+        }
+        Ok(Self {
+            target,
+            key_id: match key_id {
+                Some(key_id) => match HeaderValue::from_str(&key_id) {
+                    Ok(value) => Some(value),
+                    Err(_) => return Err(ConfigError::InvalidValue(section.key("key_id"), key_id)),
+                },
+                None => None,
+            },
+            key,
+            signature,
+            auth: AuthConfig::read(section)?,
+            tls: TlsConfig::read(section)?,
+            proxy: ProxyConfig::read(section)?,
+            batch_size: batch_size as usize,
+        })
+    }
+}
+
+pub struct WebhookSink {
+    client: Client,
+    // Used for OAuth2 token requests, which usually go to another host
+    auth_client: Client,
+    target: Target,
+    key_id: Option<HeaderValue>,
+    signer: Option<Signer>,
+    signature: SignatureScheme,
+    auth: Auth,
+    batch_size: usize,
+}
+
+// Response to a batch request, events missing in results are considered as not delivered
+#[derive(Deserialize)]
+struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+#[derive(Deserialize)]
+struct BatchResult {
+    id: Uuid,
+    // Status code as if the event was sent alone
+    status: u16,
+    #[serde(default)]
+    error: String,
+}
+
+impl WebhookSink {
+    pub fn new(config: &WebhookConfig, data_dir: &Path) -> Result<Self, SinkError> {
+        let signer = match config.key {
+            SigningKeyConfig::None => None,
+            SigningKeyConfig::Hmac(ref secret) => Some(Signer::Hmac(Hmac::new_varkey(secret.as_bytes())?)),
+            SigningKeyConfig::Ed25519(ref path) => {
+                let path = match path {
+                    Some(path) => data_dir.join(path),
+                    None => data_dir.join(DEFAULT_PRIVATE_KEY),
+                };
+                Some(Signer::Ed25519(keys::load_keypair(&path)?))
+            }
+        };
+        Ok(Self {
+            client: config.proxy.apply(config.tls.apply(Client::builder())?)?.build()?,
+            auth_client: config
+                .proxy
+                .apply(config.tls.without_identity().apply(Client::builder())?)?
+                .build()?,
+            target: config.target.clone(),
+            key_id: config.key_id.clone(),
+            signer,
+            signature: config.signature,
+            auth: Auth::new(&config.auth),
+            batch_size: config.batch_size,
+        })
+    }
+
+    fn post(&mut self, id: Option<&Uuid>, data: &[u8]) -> Result<Response, SinkError> {
+        let mut rep = self.send(id, data)?;
+        // Access token could be revoked before it expires
+        if rep.status == StatusCode::UNAUTHORIZED && self.auth.reset() {
+            rep = self.send(id, data)?;
+        }
+        if let Some(delay) = retry_after(rep.status, &rep.headers) {
+            Err(SinkError::RetryAfter(rep.status, delay))
+        } else if !rep.status.is_success() {
+            Err(SinkError::RequestFailed(rep.status, response_text(&rep.body)))
+        } else {
+            Ok(rep)
+        }
+    }
+
+    fn post_batch(&mut self, events: &[Event]) -> Result<Vec<Result<(), SinkError>>, SinkError> {
+        let data = serde_json::to_vec(events)?;
+        let rep = self.post(None, &data)?;
+        let rep: BatchResponse = serde_json::from_slice(&rep.body)
+            .map_err(|err| SinkError::InvalidResponse(format!("can not parse batch response: {}", err)))?;
+        let mut results: HashMap<Uuid, BatchResult> = rep.results.into_iter().map(|x| (x.id, x)).collect();
+        Ok(events
+            .iter()
+            .map(|event| match event.id().and_then(|id| results.remove(id)) {
+                Some(result) => match StatusCode::from_u16(result.status) {
+                    Ok(status) if status.is_success() => Ok(()),
+                    Ok(status) => Err(SinkError::RequestFailed(status, result.error)),
+                    Err(_) => Err(SinkError::InvalidResponse(format!(
+                        "invalid status {} in batch response",
+                        result.status
+                    ))),
+                },
+                None => Err(SinkError::InvalidResponse(String::from(
+                    "event is missing in batch response",
+                ))),
+            })
+            .collect())
+    }
+
+    fn send(&mut self, id: Option<&Uuid>, data: &[u8]) -> Result<Response, SinkError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        // The same key is sent on every attempt, so receiver can drop events it has already stored
+        if let Some(id) = id {
+            headers.insert(
+                HeaderName::from_static(HEADER_IDEMPOTENCY_KEY),
+                HeaderValue::from_str(&id.to_string())?,
+            );
+        }
+        // Queued events are signed with the current key on each attempt, key id tells which one it is
+        if let Some(ref key_id) = self.key_id {
+            headers.insert(HeaderName::from_static(HEADER_KEY_ID), key_id.clone());
+        }
+        if let Some(ref signer) = self.signer {
+            match self.signature {
+                SignatureScheme::Body => {
+                    headers.insert(signer.header_name(), signer.sign(&[data])?);
+                }
+                SignatureScheme::Timestamped => {
+                    // Every attempt is signed again, so redelivered events are not rejected as stale
+                    let timestamp = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|x| x.as_secs())
+                        .unwrap_or(0)
+                        .to_string();
+                    let nonce = hex::encode(rand::random::<[u8; 16]>());
+                    let signature = signer.sign(&[timestamp.as_bytes(), b".", nonce.as_bytes(), b".", data])?;
+                    headers.insert(signer.header_name(), signature);
+                    headers.insert(
+                        HeaderName::from_static(HEADER_TIMESTAMP),
+                        HeaderValue::from_str(&timestamp)?,
+                    );
+                    headers.insert(HeaderName::from_static(HEADER_NONCE), HeaderValue::from_str(&nonce)?);
+                }
+            }
+        }
+        if let Some(authorization) = self.auth.authorization(&self.auth_client)? {
+            headers.insert(AUTHORIZATION, authorization);
+        }
+        match self.target {
+            Target::Http(ref url) => {
+                let rep = self.client.post(url).headers(headers).body(data.to_vec()).send()?;
+                Ok(Response {
+                    status: rep.status(),
+                    headers: rep.headers().clone(),
+                    body: rep.bytes()?.to_vec(),
+                })
+            }
+            Target::Unix(ref socket, ref path) => unix::post(socket, path, &headers, data),
+        }
+    }
+}
+
+enum Signer {
+    Hmac(Hmac<Sha256>),
+    Ed25519(Keypair),
+}
+
+impl Signer {
+    fn header_name(&self) -> HeaderName {
+        HeaderName::from_static(match self {
+            Signer::Hmac(_) => HEADER_HMAC_SIGNATURE,
+            Signer::Ed25519(_) => HEADER_ED25519_SIGNATURE,
+        })
+    }
+
+    fn sign(&self, parts: &[&[u8]]) -> Result<HeaderValue, SinkError> {
+        let signature = match self {
+            Signer::Hmac(secret) => {
+                let mut secret = secret.clone();
+                for part in parts {
+                    secret.input(part);
+                }
+                hex::encode(secret.result().code())
+            }
+            Signer::Ed25519(keypair) => hex::encode(keypair.sign(&parts.concat()).to_bytes()),
+        };
+        Ok(HeaderValue::from_str(&signature)?)
+    }
+}
+
+impl Sink for WebhookSink {
+    fn publish(&mut self, event: &Event) -> Result<(), SinkError> {
+        let data = serde_json::to_vec(&event)?;
+        self.post(event.id(), &data).map(|_| ())
+    }
+
+    fn publish_batch(&mut self, events: &[Event], stop: &dyn Fn() -> bool) -> Vec<Result<(), SinkError>> {
+        if self.batch_size == 1 {
+            return publish_each(self, events, stop);
+        }
+        let mut results = Vec::with_capacity(events.len());
+        for chunk in events.chunks(self.batch_size) {
+            if stop() {
+                break;
+            }
+            match self.post_batch(chunk) {
+                Ok(chunk_results) => results.extend(chunk_results),
+                // Whole request is rejected, so events are sent one by one to find out which of them are invalid
+                Err(err) if err.is_permanent() => {
+                    eprintln!(
+                        "[playlog] Batch request was rejected: {}, sending events one by one",
+                        err
+                    );
+                    results.extend(publish_each(self, chunk, stop));
+                }
+                // Result of the first event tells publisher why none of the rest is sent
+                Err(err) => results.push(Err(err)),
+            }
+            if results.iter().any(|x| matches!(x, Err(err) if !err.is_permanent())) {
+                break;
+            }
+        }
+        results
+    }
+}
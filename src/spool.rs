@@ -0,0 +1,138 @@
+use crate::event::Event;
+use serde_json::Error as JsonError;
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Error as IoError, ErrorKind as IoErrorKind, Write},
+    path::PathBuf,
+};
+
+pub struct Spool {
+    path: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl Spool {
+    pub fn new<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        Self {
+            path,
+            tmp_path: PathBuf::from(tmp_path),
+        }
+    }
+
+    pub fn load(&self) -> Result<Vec<Event>, SpoolError> {
+        // Leftover from an interrupted save, the spool itself is still consistent
+        if let Err(err) = fs::remove_file(&self.tmp_path) {
+            if err.kind() != IoErrorKind::NotFound {
+                return Err(SpoolError::Io(err));
+            }
+        }
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == IoErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(SpoolError::Io(err)),
+        };
+        let mut events = Vec::new();
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(event) => events.push(event),
+                Err(err) => eprintln!(
+                    "[playlog] Skipping line {} of spool {}: {}",
+                    idx + 1,
+                    self.path.display(),
+                    err
+                ),
+            }
+        }
+        Ok(events)
+    }
+
+    pub fn append(&self, events: &[Event]) -> Result<(), SpoolError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut data = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut data, event)?;
+            data.push(b'\n');
+        }
+        let mut file = OpenOptions::new().append(true).create(true).open(&self.path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    pub fn save(&self, events: &[Event]) -> Result<(), SpoolError> {
+        if events.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Ok(()) => Ok(()),
+                Err(err) if err.kind() == IoErrorKind::NotFound => Ok(()),
+                Err(err) => Err(SpoolError::Io(err)),
+            };
+        }
+        let dir = self.path.parent();
+        if let Some(dir) = dir {
+            fs::create_dir_all(dir)?;
+        }
+        let mut writer = BufWriter::new(File::create(&self.tmp_path)?);
+        for event in events {
+            serde_json::to_writer(&mut writer, event)?;
+            writer.write_all(b"\n")?;
+        }
+        let file = writer.into_inner().map_err(IoError::from)?;
+        file.sync_all()?;
+        // rename is atomic, so the spool always contains either the old or the new queue
+        fs::rename(&self.tmp_path, &self.path)?;
+        if let Some(dir) = dir {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SpoolError {
+    Io(IoError),
+    Json(JsonError),
+}
+
+impl From<IoError> for SpoolError {
+    fn from(err: IoError) -> Self {
+        SpoolError::Io(err)
+    }
+}
+
+impl From<JsonError> for SpoolError {
+    fn from(err: JsonError) -> Self {
+        SpoolError::Json(err)
+    }
+}
+
+impl Error for SpoolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(match self {
+            SpoolError::Io(err) => err,
+            SpoolError::Json(err) => err,
+        })
+    }
+}
+
+impl fmt::Display for SpoolError {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpoolError::Io(err) => write!(out, "spool IO error: {}", err),
+            SpoolError::Json(err) => write!(out, "can not serialize spooled event: {}", err),
+        }
+    }
+}